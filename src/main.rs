@@ -21,29 +21,37 @@ fn main() {
 fn init(
     mut cmd: Commands,
     asset_server: Res<AssetServer>,
-    mut atlases: ResMut<Assets<TextureAtlas>>,
+    atlases: Res<Assets<TextureAtlas>>,
+    types: Res<TileTypes>,
 ) {
     cmd.spawn()
         .insert_bundle(OrthographicCameraBundle::new_2d());
 
-    let solid_handle = asset_server.load("solid.png");
-    let atlas = TextureAtlas::from_grid(solid_handle, Vec2::new(16.0, 16.0), 2, 1);
-    let solid_handle = atlases.add(atlas);
+    // the terrain variants follow the frames of the tilemap
+    let solid_images = vec![
+        asset_server.load("tilemap.png"),
+        asset_server.load("terrain.png"),
+    ];
+    let solid_atlas = SolidAtlasSources::new(solid_images, Vec2::new(16.0, 16.0), &atlases);
+    let solid_handle = solid_atlas.atlas.clone();
+    cmd.insert_resource(solid_atlas);
 
     let liquid_handle: Handle<Image> = asset_server.load("liquid.png");
 
     let font_handle = asset_server.load("font.ttf");
 
-    let mut tiles = WorldTiles::new(solid_handle, liquid_handle, font_handle);
+    let mut tiles = WorldTiles::new(16, solid_handle, liquid_handle, font_handle);
     let solid = &mut tiles.solid;
+    let stone = types.find("stone").unwrap_or_default();
+    let grass = types.find("grass").unwrap_or_default();
 
     for column in 0..10 {
-        *solid.tiles.get_or_create(&[column, 5]) = OptTileIndex::from_index(1);
+        *solid.tiles.get_or_create(&[column, 5]) = grass;
     }
 
     for row in 0..5 {
         for column in 0..10 {
-            *solid.tiles.get_or_create(&[column, row]) = OptTileIndex::from_index(0);
+            *solid.tiles.get_or_create(&[column, row]) = stone;
         }
     }
 
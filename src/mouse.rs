@@ -37,7 +37,7 @@ fn mouse_position(
         let transform = transform.compute_matrix();
         let camera = camera.projection_matrix;
 
-        mouse_pos.world = (camera * transform)
+        mouse_pos.world = (camera * transform.inverse())
             .inverse()
             .transform_point3(normalized.extend(0.0))
             .xy();
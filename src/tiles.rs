@@ -1,17 +1,46 @@
+use bevy::ecs::system::SystemParam;
+use bevy::input::mouse::MouseWheel;
 use bevy::prelude::*;
+use bevy::tasks::ComputeTaskPool;
+use serde::{Deserialize, Serialize};
 
+use std::marker::PhantomData;
 use std::num::NonZeroU32;
 
 use crate::mouse::MousePos;
 
+mod autotile;
+mod brush;
+mod format;
 mod generic_tiles;
+mod history;
 mod liquid;
+mod liquid_feature;
+mod liquid_kind;
+mod liquid_render;
+mod liquid_settings;
+mod reaction;
+mod solid_atlas;
 mod sync;
+mod text_format;
+mod tile_type;
 
+use brush::*;
 use generic_tiles::*;
+use history::*;
 use liquid::*;
+use liquid_feature::*;
+use liquid_kind::*;
+use liquid_render::*;
+use liquid_settings::*;
+use reaction::*;
+pub use solid_atlas::SolidAtlasSources;
+use sync::TileRenderSettings;
+pub use tile_type::TileTypes;
+use tile_type::*;
 
-#[derive(Default, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "Option<u32>", into = "Option<u32>")]
 pub struct OptTileIndex(Option<NonZeroU32>);
 
 impl OptTileIndex {
@@ -28,6 +57,18 @@ impl OptTileIndex {
     }
 }
 
+impl From<Option<u32>> for OptTileIndex {
+    fn from(index: Option<u32>) -> Self {
+        index.map(OptTileIndex::from_index).unwrap_or_default()
+    }
+}
+
+impl From<OptTileIndex> for Option<u32> {
+    fn from(index: OptTileIndex) -> Self {
+        index.get_index()
+    }
+}
+
 #[derive(Component)]
 pub struct SolidTiles {
     pub tiles: GenericTiles<i32, OptTileIndex>,
@@ -37,44 +78,100 @@ pub struct SolidTiles {
 #[derive(Component)]
 pub struct LiquidTiles {
     pub tiles: GenericTiles<i32, LiquidTile>,
+    pub kinds: LiquidKinds,
+    /// Sources and drains, run before every simulation step.
+    pub features: LiquidFeatures,
     pub image: Handle<Image>,
     pub font: Handle<Font>,
+    pub render: LiquidRenderSettings,
+    /// Volume accounting of every simulation step run in the most recent frame, in order.
+    /// Frames can run several steps to catch up, or none at all.
+    pub step_reports: Vec<LiquidStepReport>,
+}
+
+impl LiquidTiles {
+    pub fn total_volume(&self) -> f64 {
+        self.tiles.total_volume()
+    }
 }
 
 #[derive(Bundle)]
 pub struct WorldTiles {
     pub solid: SolidTiles,
     pub liquid: LiquidTiles,
+    /// Places the map in the world. Tile sprites are children of the map entity, and the
+    /// editor picks tiles through its `GlobalTransform`.
     pub transform: Transform,
+    pub global_transform: GlobalTransform,
 }
 
 impl WorldTiles {
     pub fn new(
+        chunk_size: usize,
         solid_atlas: Handle<TextureAtlas>,
         liquid_material: Handle<Image>,
         liquid_font: Handle<Font>,
     ) -> Self {
         WorldTiles {
             solid: SolidTiles {
-                tiles: GenericTiles::new(16),
+                tiles: GenericTiles::new(chunk_size),
                 atlas: solid_atlas,
             },
             liquid: LiquidTiles {
-                tiles: GenericTiles::new(16),
+                tiles: GenericTiles::new(chunk_size),
+                kinds: LiquidKinds::default(),
+                features: LiquidFeatures::default(),
                 image: liquid_material,
                 font: liquid_font,
+                render: Default::default(),
+                step_reports: Vec::new(),
             },
             transform: Default::default(),
+            global_transform: Default::default(),
         }
     }
 }
 
+/// Tile of a map with the given `GlobalTransform` under a point in world space.
+pub fn world_to_tile(transform: &GlobalTransform, tile_size: Vec2, world: Vec2) -> [i32; 2] {
+    let local = transform
+        .compute_matrix()
+        .inverse()
+        .transform_point3(world.extend(0.0));
+    let tile = local.truncate() / tile_size;
+    [tile.x.round() as i32, tile.y.round() as i32]
+}
+
+/// Resources `liquid_sim` reads every frame.
+#[derive(SystemParam)]
+struct LiquidSimResources<'w, 's> {
+    settings: Res<'w, LiquidSimSettings>,
+    reactions: Res<'w, LiquidReactions>,
+    types: Res<'w, TileTypes>,
+    time: Res<'w, Time>,
+    pool: Res<'w, ComputeTaskPool>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
 fn liquid_sim(
     mut enabled: Local<bool>,
+    mut clock: Local<LiquidSimClock>,
+    mut mode: ResMut<LiquidStepMode>,
     keys: Res<Input<KeyCode>>,
-    mut query: Query<(&SolidTiles, &mut LiquidTiles)>,
-    time: Res<Time>,
+    resources: LiquidSimResources,
+    mut reaction_events: EventWriter<LiquidReactionEvent>,
+    mut query: Query<(Entity, &mut SolidTiles, &mut LiquidTiles)>,
 ) {
+    let LiquidSimResources {
+        settings,
+        reactions,
+        types,
+        time,
+        pool,
+        ..
+    } = &resources;
+
     if keys.just_pressed(KeyCode::Space) {
         *enabled = !*enabled;
         eprintln!(
@@ -83,17 +180,85 @@ fn liquid_sim(
         );
     }
 
-    if *enabled || keys.just_pressed(KeyCode::S) {
-        for (solid, mut liquid) in query.iter_mut() {
-            liquid.tiles.step(&solid.tiles, 0.1);
+    if keys.just_pressed(KeyCode::D) {
+        *mode = match *mode {
+            LiquidStepMode::InPlace => LiquidStepMode::Deterministic,
+            LiquidStepMode::Deterministic => LiquidStepMode::InPlace,
+        };
+        eprintln!("liquid step mode {:?}", *mode);
+    }
+
+    let mut steps = if *enabled {
+        clock.advance(time.delta_seconds(), settings)
+    } else {
+        0
+    };
+    if keys.just_pressed(KeyCode::S) {
+        steps += 1;
+    }
+
+    for (entity, mut solid, mut liquid) in query.iter_mut() {
+        let liquid = &mut *liquid;
+        liquid.step_reports.clear();
+        for (coord, _) in solid.tiles.indexed_modified_tiles() {
+            liquid.tiles.wake_around(&coord);
+        }
+
+        for _ in 0..steps {
+            let view = SolidView::new(&solid.tiles, types);
+            let mut report = liquid.tiles.step_with_features(
+                view,
+                &liquid.features,
+                &liquid.kinds,
+                settings,
+                *mode,
+                Some(pool),
+            );
+
+            let outcomes = liquid.tiles.react(
+                &mut solid.tiles,
+                types,
+                &liquid.kinds,
+                reactions,
+                settings.empty_threshold,
+            );
+            report.consumed = outcomes.iter().map(|outcome| outcome.consumed as f64).sum();
+            liquid.step_reports.push(report);
+            for outcome in &outcomes {
+                liquid.tiles.wake_around(&outcome.coord);
+                liquid.tiles.wake_around(&outcome.partner_coord);
+            }
+            reaction_events.send_batch(outcomes.into_iter().map(|outcome| LiquidReactionEvent {
+                entity,
+                reaction: outcome.reaction,
+                coord: outcome.coord,
+                partner_coord: outcome.partner_coord,
+                consumed: outcome.consumed,
+            }));
         }
     }
 }
 
-fn clear_modified_solid(mut solid: Query<&mut SolidTiles>) {
-    for mut solid in solid.iter_mut() {
-        solid.tiles.clear_modified();
-    }
+/// Reaction table loaded at startup, the built-in reactions are used when it is missing.
+const REACTIONS_FILE: &str = "assets/reactions.ron";
+/// Tile types loaded at startup, the built-in types are used when it is missing.
+const TILE_TYPES_FILE: &str = "assets/tile_types.ron";
+/// Liquid settings loaded at startup, the defaults are used when it is missing.
+const LIQUID_SETTINGS_FILE: &str = "assets/liquid_settings.ron";
+
+/// Reads a RON config file, falling back to the default when it is missing or invalid.
+fn load_config<T: Default>(file: &str, parse: impl FnOnce(&str) -> Result<T, ron::Error>) -> T {
+    let text = match std::fs::read_to_string(file) {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", file, err);
+            return T::default();
+        }
+    };
+    parse(&text).unwrap_or_else(|err| {
+        eprintln!("failed to load {}: {}", file, err);
+        T::default()
+    })
 }
 
 pub struct TilesPlugin;
@@ -103,32 +268,267 @@ impl Plugin for TilesPlugin {
         let mut tiles_stage = SystemStage::single_threaded();
         tiles_stage
             .add_system(liquid_sim)
+            .add_system(solid_atlas::build_solid_atlas)
             .add_system(sync::tiles_sync::<SolidTiles>)
-            .add_system(sync::tiles_sync::<LiquidTiles>)
-            .add_system(clear_modified_solid);
+            .add_system(sync::tiles_sync::<LiquidTiles>);
 
-        app.insert_resource(bevy_immediate::ImmediateRenderSettings {
-            tile_size: Vec2::splat(16.0),
-            ..default()
-        })
-        .add_plugin(bevy_immediate::ImmediateRenderPlugin)
-        .add_stage("tiles", tiles_stage);
+        app
+            // unlike the `InPlace` default, this mode runs on the compute task pool
+            .insert_resource(LiquidStepMode::Deterministic)
+            .insert_resource(load_config(
+                LIQUID_SETTINGS_FILE,
+                LiquidSimSettings::from_ron,
+            ))
+            .insert_resource(load_config(REACTIONS_FILE, LiquidReactions::from_ron))
+            .insert_resource(load_config(TILE_TYPES_FILE, TileTypes::from_ron))
+            .add_event::<LiquidReactionEvent>()
+            .insert_resource(TileRenderSettings {
+                tile_size: Vec2::splat(16.0),
+                ..default()
+            })
+            .add_stage("tiles", tiles_stage);
     }
 }
 
+/// Amount of liquid per step added by sources and removed by drains placed in the editor.
+const FEATURE_RATE: f32 = 0.1;
+
+/// Keys that pick the first nine tile types of the palette.
+const TILE_TYPE_KEYS: [KeyCode; 9] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
 #[derive(Default)]
 struct EditorState {
     liquid: bool,
+    liquid_kind: LiquidKind,
+    /// Index into `TileTypes` painted on the solid layer.
+    tile_type: u32,
+    brush: Brush,
+    tool: EditorTool,
+    /// Where the current drag of a line or rectangle tool or the current bucket click started,
+    /// and what it does.
+    drag: Option<([i32; 2], EditAction)>,
+    /// Map and tile the brush was on the frame before, while it is painting.
+    last_brush_pos: Option<(Entity, [i32; 2])>,
+    scroll: ScrollSteps,
+}
+
+/// What the held mouse button or key does to the painted tiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EditAction {
+    Paint,
+    Erase,
+    /// Fills liquid tiles far over capacity.
+    Overfill,
+    PlaceSource,
+    PlaceDrain,
+    RemoveFeature,
+}
+
+impl EditAction {
+    fn current(keys: &Input<KeyCode>, mouse_keys: &Input<MouseButton>) -> Option<Self> {
+        if mouse_keys.pressed(MouseButton::Left) {
+            Some(EditAction::Paint)
+        } else if mouse_keys.pressed(MouseButton::Right) {
+            Some(EditAction::Erase)
+        } else if keys.pressed(KeyCode::M) {
+            Some(EditAction::Overfill)
+        } else if keys.pressed(KeyCode::O) {
+            Some(EditAction::PlaceSource)
+        } else if keys.pressed(KeyCode::P) {
+            Some(EditAction::PlaceDrain)
+        } else if keys.pressed(KeyCode::U) {
+            Some(EditAction::RemoveFeature)
+        } else {
+            None
+        }
+    }
+}
+
+/// Applies `action` to the tile at `pos` of the layer the editor works on, recording the
+/// change in `stroke`.
+fn edit_tile(
+    state: &EditorState,
+    action: EditAction,
+    pos: [i32; 2],
+    stroke: &mut Stroke,
+    solid: &mut SolidTiles,
+    liquid: &mut LiquidTiles,
+) {
+    if !state.liquid {
+        let before = solid.tiles.get_or_default(&pos);
+        let tile = match action {
+            EditAction::Paint => OptTileIndex::from_index(state.tile_type),
+            EditAction::Erase => OptTileIndex::empty(),
+            _ => return,
+        };
+        if tile != before {
+            stroke.solid.record(pos, before, tile);
+            solid.tiles.set(&pos, tile);
+        }
+        return;
+    }
+
+    let tile = match action {
+        EditAction::Paint => Some(LiquidTile::with_kind(1.0, state.liquid_kind)),
+        EditAction::Erase => Some(LiquidTile::new(0.0)),
+        EditAction::Overfill => Some(LiquidTile::with_kind(99.0, state.liquid_kind)),
+        _ => None,
+    };
+    if let Some(tile) = tile {
+        stroke
+            .liquid
+            .record(pos, liquid.tiles.get_or_default(&pos), tile.clone());
+        liquid.tiles.set(&pos, tile);
+        liquid.tiles.wake_around(&pos);
+        return;
+    }
+
+    let before = liquid.features.get(&pos).copied();
+    let feature = match action {
+        EditAction::PlaceSource => Some(LiquidFeature::Source {
+            liquid: state.liquid_kind,
+            rate: FEATURE_RATE,
+        }),
+        EditAction::PlaceDrain => Some(LiquidFeature::Drain { rate: FEATURE_RATE }),
+        _ => None,
+    };
+    if feature != before {
+        stroke.features.record(pos, before, feature);
+        match feature {
+            Some(feature) => liquid.features.insert(pos, feature),
+            None => liquid.features.remove(&pos),
+        };
+        // redraws the marker
+        liquid.tiles.mark_modified(&pos);
+    }
+}
+
+/// Largest region the bucket tool fills.
+const BUCKET_MAX_AREA: usize = 4096;
+
+/// Tiles the bucket tool fills when clicked at `pos`: the connected tiles of the same solid
+/// type, or in liquid mode the connected open tiles that are empty or hold the same liquid as
+/// the clicked one, with tiles below `threshold` counting as empty. `None` when there are
+/// more than `BUCKET_MAX_AREA`.
+fn bucket_region(
+    state: &EditorState,
+    types: &TileTypes,
+    pos: [i32; 2],
+    solid: &SolidTiles,
+    liquid: &LiquidTiles,
+    threshold: f32,
+) -> Option<Vec<[i32; 2]>> {
+    let bounds = FillBounds {
+        max_area: BUCKET_MAX_AREA,
+        rect: None,
+    };
+    if !state.liquid {
+        let start = solid.tiles.get_or_default(&pos);
+        return solid
+            .tiles
+            .flood_fill(pos, bounds, |_, tile| *tile == start);
+    }
+
+    let view = SolidView::new(&solid.tiles, types);
+    let start = liquid.tiles.get_or_default(&pos);
+    liquid.tiles.flood_fill(pos, bounds, |point, tile| {
+        !view.blocks_liquid(&point)
+            && tile.is_empty(threshold) == start.is_empty(threshold)
+            && (tile.is_empty(threshold) || tile.kind == start.kind)
+    })
+}
+
+/// Map the editor works on. With `None` it edits the map under the cursor.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SelectedMap(pub Option<Entity>);
+
+type EditorMaps<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static GlobalTransform,
+        &'static mut SolidTiles,
+        &'static mut LiquidTiles,
+    ),
+>;
+
+/// The selected map, or the topmost map with a chunk under the cursor. Without one it falls
+/// back to the topmost map, so painting also works where no chunk exists yet.
+fn editor_target(
+    selected: &SelectedMap,
+    mouse_pos: &MousePos,
+    tile_size: Vec2,
+    maps: &EditorMaps,
+) -> Option<Entity> {
+    if selected.0.is_some() {
+        return selected.0;
+    }
+    let z = |(_, transform, ..): &(Entity, &GlobalTransform, _, _)| transform.translation.z;
+    let topmost = |a: &_, b: &_| z(a).total_cmp(&z(b));
+    maps.iter()
+        .filter(|(_, transform, solid, liquid)| {
+            let pos = world_to_tile(transform, tile_size, mouse_pos.get_world());
+            let solid_chunk = solid.tiles.point_to_chunk_coord(&pos);
+            let liquid_chunk = liquid.tiles.point_to_chunk_coord(&pos);
+            solid.tiles.chunks().contains_key(&solid_chunk)
+                || liquid.tiles.chunks().contains_key(&liquid_chunk)
+        })
+        .max_by(topmost)
+        .or_else(|| maps.iter().max_by(topmost))
+        .map(|(entity, ..)| entity)
+}
+
+/// Keyboard and mouse state `tiles_editor` reads.
+#[derive(SystemParam)]
+struct EditorInput<'w, 's> {
+    keys: Res<'w, Input<KeyCode>>,
+    mouse_keys: Res<'w, Input<MouseButton>>,
+    mouse_pos: Res<'w, MousePos>,
+    wheel: EventReader<'w, 's, MouseWheel>,
+}
+
+/// Settings and registries `tiles_editor` reads.
+#[derive(SystemParam)]
+struct EditorResources<'w, 's> {
+    render_settings: Res<'w, TileRenderSettings>,
+    types: Res<'w, TileTypes>,
+    liquid_settings: Res<'w, LiquidSimSettings>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
 }
 
 fn tiles_editor(
-    mut editor_state: Local<EditorState>,
-    keys: Res<Input<KeyCode>>,
-    mouse_keys: Res<Input<MouseButton>>,
-    mouse_pos: Res<MousePos>,
-    mut solid: Query<&mut SolidTiles>,
-    mut liquid: Query<&mut LiquidTiles>,
+    mut editor_state: ResMut<EditorState>,
+    mut selected: ResMut<SelectedMap>,
+    mut history: ResMut<EditHistory>,
+    input: EditorInput,
+    resources: EditorResources,
+    mut maps: EditorMaps,
 ) {
+    let EditorInput {
+        keys,
+        mouse_keys,
+        mouse_pos,
+        mut wheel,
+    } = input;
+    let EditorResources {
+        render_settings,
+        types,
+        liquid_settings,
+        ..
+    } = resources;
+
     if keys.just_pressed(KeyCode::L) {
         editor_state.liquid = !editor_state.liquid;
         eprintln!(
@@ -141,45 +541,344 @@ fn tiles_editor(
         );
     }
 
-    if keys.just_pressed(KeyCode::C) {
-        eprintln!("clear");
-        for mut solid in solid.iter_mut() {
-            solid.tiles.clear();
+    if keys.just_pressed(KeyCode::Tab) {
+        let mut entities = maps.iter().map(|(entity, ..)| entity).collect::<Vec<_>>();
+        entities.sort();
+        selected.0 = match selected
+            .0
+            .and_then(|s| entities.iter().position(|&e| e == s))
+        {
+            Some(index) => entities.get(index + 1).copied(),
+            None => entities.first().copied(),
+        };
+        match selected.0 {
+            Some(entity) => eprintln!("editing map {:?}", entity),
+            None => eprintln!("editing the map under the cursor"),
         }
-        for mut liquid in liquid.iter_mut() {
-            liquid.tiles.clear();
+    }
+    if let Some(entity) = selected.0 {
+        if maps.get(entity).is_err() {
+            selected.0 = None;
         }
     }
 
-    //if !mouse_keys.pressed(MouseButton::Left) && !mouse_keys.pressed(MouseButton::Right) {
-    //return;
-    //}
+    if keys.just_pressed(KeyCode::K) {
+        // kinds are registered per map
+        let target = editor_target(&selected, &mouse_pos, render_settings.tile_size, &maps);
+        if let Some((_, _, _, liquid)) = target.and_then(|entity| maps.get(entity).ok()) {
+            editor_state.liquid_kind = liquid.kinds.next(editor_state.liquid_kind);
+            eprintln!(
+                "liquid kind {}",
+                liquid.kinds.get(editor_state.liquid_kind).name
+            );
+        }
+    }
 
-    let pos = mouse_pos.get_world() / 16.0;
-    let pos = [pos.x.round() as i32, pos.y.round() as i32];
+    if keys.just_pressed(KeyCode::T) {
+        editor_state.tile_type = types.next(editor_state.tile_type);
+        eprintln!("tile type {}", types.get(editor_state.tile_type).name);
+    }
+    if let Some(index) = TILE_TYPE_KEYS
+        .iter()
+        .position(|&key| keys.just_pressed(key))
+    {
+        if let Some((_, def)) = types.iter().nth(index) {
+            editor_state.tile_type = index as u32;
+            eprintln!("tile type {}", def.name);
+        }
+    }
 
-    if editor_state.liquid {
-        for mut liquid in liquid.iter_mut() {
-            let tile = liquid.tiles.get_or_create(&pos);
-            if mouse_keys.pressed(MouseButton::Left) {
-                *tile = LiquidTile::new(1.0);
-            }
-            if mouse_keys.pressed(MouseButton::Right) {
-                *tile = LiquidTile::new(0.0);
+    if keys.just_pressed(KeyCode::B) {
+        editor_state.brush.shape = editor_state.brush.shape.next();
+        eprintln!("brush {:?}", editor_state.brush.shape);
+    }
+
+    let mut radius = editor_state.brush.radius;
+    if keys.just_pressed(KeyCode::LBracket) {
+        radius -= 1;
+    }
+    if keys.just_pressed(KeyCode::RBracket) {
+        radius += 1;
+    }
+    for event in wheel.iter() {
+        radius += editor_state.scroll.add(event);
+    }
+    let radius = radius.clamp(0, MAX_BRUSH_RADIUS);
+    if radius != editor_state.brush.radius {
+        editor_state.brush.radius = radius;
+        eprintln!("brush radius {}", radius);
+    }
+
+    if keys.just_pressed(KeyCode::N) {
+        editor_state.tool = editor_state.tool.next();
+        editor_state.drag = None;
+        eprintln!("tool {:?}", editor_state.tool);
+    }
+
+    if keys.just_pressed(KeyCode::F1) {
+        for (_, _, _, mut liquid) in maps.iter_mut() {
+            liquid.render.debug_labels = !liquid.render.debug_labels;
+            liquid.tiles.mark_all_modified();
+        }
+    }
+
+    if keys.just_pressed(KeyCode::F2) {
+        for (_, _, _, mut liquid) in maps.iter_mut() {
+            liquid.render.mode = match liquid.render.mode {
+                LiquidRenderMode::Tiles => LiquidRenderMode::Level,
+                LiquidRenderMode::Level => LiquidRenderMode::Tiles,
+            };
+            liquid.tiles.mark_all_modified();
+            eprintln!("liquid render mode {:?}", liquid.render.mode);
+        }
+    }
+
+    let ctrl = keys.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    if ctrl && keys.just_pressed(KeyCode::Z) {
+        let shift = keys.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+        let stroke = if shift {
+            history.redo()
+        } else {
+            history.undo()
+        };
+        if let Some(stroke) = stroke {
+            if let Ok((_, _, mut solid, mut liquid)) = maps.get_mut(stroke.map) {
+                stroke.apply(&mut solid, &mut liquid, shift);
             }
-            if keys.pressed(KeyCode::M) {
-                *tile = LiquidTile::new(99.0);
+        }
+        return;
+    }
+
+    let target = editor_target(&selected, &mouse_pos, render_settings.tile_size, &maps);
+    let (entity, transform, mut solid, mut liquid) = match target.and_then(|e| maps.get_mut(e).ok())
+    {
+        Some(map) => map,
+        None => {
+            editor_state.last_brush_pos = None;
+            return;
+        }
+    };
+
+    if keys.just_pressed(KeyCode::C) {
+        eprintln!("clear");
+        solid.tiles.clear();
+        liquid.tiles.clear();
+        liquid.features.clear();
+        history.forget(entity);
+    }
+
+    let pos = world_to_tile(transform, render_settings.tile_size, mouse_pos.get_world());
+    let action = EditAction::current(&keys, &mouse_keys);
+    let points = match (editor_state.tool, action) {
+        (EditorTool::Brush, Some(action)) => {
+            let last = match editor_state.last_brush_pos.replace((entity, pos)) {
+                Some((last_entity, last)) if last_entity == entity => last,
+                _ => pos,
+            };
+            let points = editor_state.tool.points(&editor_state.brush, last, pos);
+            Some((action, points))
+        }
+        (EditorTool::Bucket, Some(action)) => {
+            if editor_state.drag.is_some() {
+                None
+            } else {
+                // fills once per click, not every frame the button is held
+                editor_state.drag = Some((pos, action));
+                let region = bucket_region(
+                    &editor_state,
+                    &types,
+                    pos,
+                    &solid,
+                    &liquid,
+                    liquid_settings.empty_threshold,
+                );
+                if region.is_none() {
+                    eprintln!("fill area too large");
+                }
+                region.map(|region| (action, region.into_iter().collect()))
             }
         }
+        (_, Some(action)) => {
+            editor_state.drag.get_or_insert((pos, action));
+            None
+        }
+        (tool, None) => editor_state
+            .drag
+            .take()
+            .map(|(start, action)| (action, tool.points(&editor_state.brush, start, pos))),
+    };
+
+    if let Some((action, points)) = points {
+        let stroke = history.stroke(entity);
+        for point in points {
+            edit_tile(
+                &editor_state,
+                action,
+                point,
+                stroke,
+                &mut solid,
+                &mut liquid,
+            );
+        }
+    }
+    if action.is_none() {
+        editor_state.last_brush_pos = None;
+        history.finish_stroke();
+    }
+}
+
+/// Translucent sprites of the selected tile type under the brush.
+#[derive(Default)]
+struct EditorPreview {
+    /// Map, tile, tile type and brush the sprites were spawned for.
+    shown: Option<(Entity, [i32; 2], u32, Brush)>,
+    sprites: Vec<Entity>,
+}
+
+fn editor_preview(
+    mut cmd: Commands,
+    mut preview: Local<EditorPreview>,
+    editor_state: Res<EditorState>,
+    (selected, mouse_pos): (Res<SelectedMap>, Res<MousePos>),
+    render_settings: Res<TileRenderSettings>,
+    types: Res<TileTypes>,
+    maps: EditorMaps,
+) {
+    let tile_size = render_settings.tile_size;
+    // liquid is painted without a preview
+    let target = if editor_state.liquid {
+        None
     } else {
-        for mut solid in solid.iter_mut() {
-            let tile = solid.tiles.get_or_create(&pos);
-            if mouse_keys.pressed(MouseButton::Left) {
-                *tile = OptTileIndex::from_index(0);
+        editor_target(&selected, &mouse_pos, tile_size, &maps)
+    };
+    let map = target.and_then(|entity| maps.get(entity).ok());
+    let shown = map.map(|(entity, transform, ..)| {
+        let pos = world_to_tile(transform, tile_size, mouse_pos.get_world());
+        (entity, pos, editor_state.tile_type, editor_state.brush)
+    });
+    if shown == preview.shown {
+        return;
+    }
+    preview.shown = shown;
+    for sprite in preview.sprites.drain(..) {
+        cmd.entity(sprite).despawn_recursive();
+    }
+
+    let ((entity, _, solid, _), (_, pos, tile_type, brush)) = match map.zip(shown) {
+        Some(shown) => shown,
+        None => return,
+    };
+    let index = types.get(tile_type).atlas_index as usize;
+    for [x, y] in brush.points(pos) {
+        // above the tiles of the map
+        let center = (Vec2::new(x as f32, y as f32) * tile_size).extend(render_settings.z + 1.0);
+        let sprite = sync::TileObject::SheetSprite {
+            index,
+            color: Color::rgba(1.0, 1.0, 1.0, 0.5),
+            atlas: solid.atlas.clone(),
+        };
+        preview
+            .sprites
+            .push(sprite.spawn(&mut cmd, center, tile_size));
+    }
+    cmd.entity(entity).push_children(&preview.sprites);
+}
+
+/// Prints the reactions on maps that show debug labels.
+fn log_reactions(
+    mut events: EventReader<LiquidReactionEvent>,
+    reactions: Res<LiquidReactions>,
+    maps: Query<&LiquidTiles>,
+) {
+    for event in events.iter() {
+        if maps
+            .get(event.entity)
+            .map_or(false, |liquid| liquid.render.debug_labels)
+        {
+            eprintln!(
+                "{} at {:?} and {:?} used up {}",
+                reactions.reactions[event.reaction].name,
+                event.coord,
+                event.partner_coord,
+                event.consumed
+            );
+        }
+    }
+}
+
+const WORLD_FILE: &str = "world.tw";
+const WORLD_TEXT_FILE: &str = "world.ron";
+
+/// Saves and loads the map the editor works on.
+fn tiles_save_load(
+    keys: Res<Input<KeyCode>>,
+    selected: Res<SelectedMap>,
+    mut history: ResMut<EditHistory>,
+    mouse_pos: Res<MousePos>,
+    render_settings: Res<TileRenderSettings>,
+    mut maps: EditorMaps,
+) {
+    let keys_used = [KeyCode::F5, KeyCode::F6, KeyCode::F9, KeyCode::F10];
+    if !keys.any_just_pressed(keys_used) {
+        return;
+    }
+    let target = editor_target(&selected, &mouse_pos, render_settings.tile_size, &maps);
+    let (entity, _, mut solid, mut liquid) = match target.and_then(|e| maps.get_mut(e).ok()) {
+        Some(map) => map,
+        None => {
+            eprintln!("no map selected or under the cursor");
+            return;
+        }
+    };
+
+    if keys.just_pressed(KeyCode::F5) {
+        match format::save_world_file(WORLD_FILE, &solid.tiles, &liquid.tiles, &liquid.features) {
+            Ok(()) => eprintln!("saved {}", WORLD_FILE),
+            Err(err) => eprintln!("failed to save {}: {}", WORLD_FILE, err),
+        }
+    }
+
+    if keys.just_pressed(KeyCode::F9) {
+        match format::load_world_file(WORLD_FILE) {
+            Ok((solid_tiles, liquid_tiles, features)) => {
+                solid.tiles = solid_tiles;
+                liquid.tiles = liquid_tiles;
+                liquid.features = features;
+                // loaded layers come without modified tiles, so rebuild all of them
+                solid.tiles.mark_all_modified();
+                liquid.tiles.mark_all_modified();
+                history.forget(entity);
+                eprintln!("loaded {}", WORLD_FILE);
             }
-            if mouse_keys.pressed(MouseButton::Right) {
-                *tile = OptTileIndex::empty();
+            Err(err) => eprintln!("failed to load {}: {}", WORLD_FILE, err),
+        }
+    }
+
+    if keys.just_pressed(KeyCode::F6) {
+        match text_format::save_world_text_file(
+            WORLD_TEXT_FILE,
+            &solid.tiles,
+            &liquid.tiles,
+            &liquid.features,
+        ) {
+            Ok(()) => eprintln!("exported {}", WORLD_TEXT_FILE),
+            Err(err) => eprintln!("failed to export {}: {}", WORLD_TEXT_FILE, err),
+        }
+    }
+
+    if keys.just_pressed(KeyCode::F10) {
+        match text_format::load_world_text_file(WORLD_TEXT_FILE) {
+            Ok((solid_tiles, liquid_tiles, features)) => {
+                solid.tiles = solid_tiles;
+                liquid.tiles = liquid_tiles;
+                liquid.features = features;
+                // loaded layers come without modified tiles, so rebuild all of them
+                solid.tiles.mark_all_modified();
+                liquid.tiles.mark_all_modified();
+                history.forget(entity);
+                eprintln!("imported {}", WORLD_TEXT_FILE);
             }
+            Err(err) => eprintln!("failed to import {}: {}", WORLD_TEXT_FILE, err),
         }
     }
 }
@@ -188,6 +887,12 @@ pub struct TilesEditorPlugin;
 
 impl Plugin for TilesEditorPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(tiles_editor);
+        app.insert_resource(SelectedMap::default())
+            .insert_resource(EditHistory::default())
+            .init_resource::<EditorState>()
+            .add_system(tiles_editor)
+            .add_system(editor_preview)
+            .add_system(log_reactions)
+            .add_system(tiles_save_load);
     }
 }
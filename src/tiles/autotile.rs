@@ -0,0 +1,198 @@
+use serde::{Deserialize, Serialize};
+
+use super::generic_tiles::*;
+use super::tile_type::TileTypes;
+use super::OptTileIndex;
+
+/// How the variants of an autotiled tile type are laid out in the atlas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AutotileLayout {
+    /// 16 variants, picked by the 4-bit mask of connected edge neighbours: north 1, east 2,
+    /// south 4 and west 8.
+    Edges,
+    /// 47 variants of the blob layout. Corners only count when both edges next to them are
+    /// connected too, and variants are ordered by their 8-bit mask: north 1, north-east 2,
+    /// east 4, south-east 8, south 16, south-west 32, west 64 and north-west 128.
+    Blob,
+}
+
+/// Picks the atlas variant of a tile type from its neighbours. The variants follow the
+/// `TileDef::atlas_index` of the type.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutotileRule {
+    pub layout: AutotileLayout,
+    /// Names of other tile types this one joins up with. It always joins up with itself.
+    #[serde(default)]
+    pub connects_to: Vec<String>,
+}
+
+impl TileTypes {
+    /// Atlas index to draw the tile at `point` with, or `None` for an empty tile. Variants past
+    /// the end of an atlas with `atlas_len` frames fall back to `TileDef::atlas_index`.
+    pub fn atlas_index(
+        &self,
+        tiles: &GenericTiles<i32, OptTileIndex>,
+        point: [i32; 2],
+        atlas_len: usize,
+    ) -> Option<u32> {
+        let tile_type = tiles.get(&point)?.get_index()?;
+        let def = self.get(tile_type);
+        let rule = match &def.autotile {
+            Some(rule) => rule,
+            None => return Some(def.atlas_index),
+        };
+
+        let [x, y] = point;
+        let connects = |[dx, dy]: [i32; 2]| {
+            tiles
+                .get(&[x + dx, y + dy])
+                .and_then(|tile| tile.get_index())
+                .map_or(false, |other| {
+                    other == tile_type || rule.connects_to.contains(&self.get(other).name)
+                })
+        };
+        let variant = match rule.layout {
+            AutotileLayout::Edges => edge_mask(connects),
+            AutotileLayout::Blob => BLOB_VARIANTS[blob_mask(connects) as usize] as u32,
+        };
+        let index = def.atlas_index + variant;
+        if index as usize >= atlas_len {
+            return Some(def.atlas_index);
+        }
+        Some(index)
+    }
+}
+
+fn edge_mask(connects: impl Fn([i32; 2]) -> bool) -> u32 {
+    [[0, 1], [1, 0], [0, -1], [-1, 0]]
+        .into_iter()
+        .enumerate()
+        .filter(|&(_, offset)| connects(offset))
+        .fold(0, |mask, (bit, _)| mask | 1 << bit)
+}
+
+/// Neighbours in the bit order of `AutotileLayout::Blob`, clockwise from north.
+const BLOB_NEIGHBOURS: [[i32; 2]; 8] = [
+    [0, 1],
+    [1, 1],
+    [1, 0],
+    [1, -1],
+    [0, -1],
+    [-1, -1],
+    [-1, 0],
+    [-1, 1],
+];
+
+fn blob_mask(connects: impl Fn([i32; 2]) -> bool) -> u8 {
+    let mask = BLOB_NEIGHBOURS
+        .into_iter()
+        .enumerate()
+        .filter(|&(_, offset)| connects(offset))
+        .fold(0, |mask, (bit, _)| mask | 1 << bit);
+    reduce_blob_mask(mask)
+}
+
+/// Clears the corner bits whose two neighbouring edges are not both set.
+const fn reduce_blob_mask(mask: u8) -> u8 {
+    let mut reduced = mask & 0b0101_0101;
+    let mut corner = 1;
+    while corner < 8 {
+        let before = 1 << (corner - 1);
+        let after = 1 << ((corner + 1) % 8);
+        if mask & (1 << corner) != 0 && mask & before != 0 && mask & after != 0 {
+            reduced |= 1 << corner;
+        }
+        corner += 2;
+    }
+    reduced
+}
+
+/// Variant for every reduced blob mask. Masks that cannot come out of `reduce_blob_mask` are
+/// left at 0.
+const BLOB_VARIANTS: [u8; 256] = {
+    let mut variants = [0; 256];
+    let mut next = 0;
+    let mut mask = 0;
+    while mask < 256 {
+        if reduce_blob_mask(mask as u8) == mask as u8 {
+            variants[mask] = next;
+            next += 1;
+        }
+        mask += 1;
+    }
+    variants
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tiles::tile_type::TileDef;
+
+    fn types(layout: AutotileLayout) -> (TileTypes, OptTileIndex) {
+        let mut types = TileTypes::default();
+        let terrain = types.register(TileDef {
+            autotile: Some(AutotileRule {
+                layout,
+                connects_to: vec![],
+            }),
+            ..TileDef::wall("terrain", 10)
+        });
+        (types, terrain)
+    }
+
+    #[test]
+    fn edges_follow_neighbours() {
+        let (types, terrain) = types(AutotileLayout::Edges);
+        let mut tiles = GenericTiles::new(4);
+        for point in [[0, 0], [0, 1], [1, 0], [-1, -1]] {
+            tiles.set(&point, terrain);
+        }
+        tiles.set(&[-1, 0], OptTileIndex::from_index(1));
+
+        // north and east connect, the other type in the west and the diagonal do not
+        assert_eq!(types.atlas_index(&tiles, [0, 0], 26), Some(10 + 0b0011));
+        assert_eq!(types.atlas_index(&tiles, [-1, 0], 26), Some(1));
+        assert_eq!(types.atlas_index(&tiles, [5, 5], 26), None);
+        // an atlas without the variant draws the first one
+        assert_eq!(types.atlas_index(&tiles, [0, 0], 12), Some(10));
+    }
+
+    #[test]
+    fn shipped_rule_fits_the_atlas() {
+        let types = TileTypes::from_ron(include_str!("../../assets/tile_types.ron")).unwrap();
+        let terrain = types.find("terrain").unwrap();
+        let mut tiles = GenericTiles::new(4);
+        for point in [[0, 0], [0, 1], [1, 0], [0, -1], [-1, 0]] {
+            tiles.set(&point, terrain);
+        }
+        // the last variant is the last frame of the shipped atlas, the 4 frames of tilemap.png
+        // followed by the 16 of terrain.png
+        assert_eq!(types.atlas_index(&tiles, [0, 0], 20), Some(19));
+    }
+
+    #[test]
+    fn blob_has_47_variants() {
+        let reduced = (0..=255u8)
+            .filter(|&mask| reduce_blob_mask(mask) == mask)
+            .count();
+        assert_eq!(reduced, 47);
+        assert_eq!(BLOB_VARIANTS[0xff], 46);
+
+        let (types, terrain) = types(AutotileLayout::Blob);
+        let mut tiles = GenericTiles::new(4);
+        for x in -1..=1 {
+            for y in -1..=1 {
+                tiles.set(&[x, y], terrain);
+            }
+        }
+        assert_eq!(types.atlas_index(&tiles, [0, 0], 57), Some(10 + 46));
+
+        // without the east edge the east corners no longer count
+        tiles.set(&[1, 0], OptTileIndex::empty());
+        let mask = 0b1100_0001 | 0b0011_0000;
+        assert_eq!(
+            types.atlas_index(&tiles, [0, 0], 57),
+            Some(10 + BLOB_VARIANTS[mask] as u32)
+        );
+    }
+}
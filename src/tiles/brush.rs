@@ -0,0 +1,205 @@
+use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
+use std::collections::BTreeSet;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BrushShape {
+    #[default]
+    Square,
+    Circle,
+}
+
+impl BrushShape {
+    pub fn next(self) -> Self {
+        match self {
+            BrushShape::Square => BrushShape::Circle,
+            BrushShape::Circle => BrushShape::Square,
+        }
+    }
+}
+
+/// Largest brush radius the editor allows.
+pub const MAX_BRUSH_RADIUS: i32 = 16;
+
+/// Pixels of scrolling, e.g. on a trackpad, that count as one line of a mouse wheel.
+const PIXELS_PER_LINE: f32 = 32.0;
+
+/// Turns mouse wheel events into whole steps of the brush radius. Parts of a step add up over
+/// the following events, so trackpads that scroll by pixels do not step on every event.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ScrollSteps {
+    lines: f32,
+}
+
+impl ScrollSteps {
+    /// Whole steps `event` completes. Horizontal scrolling has no effect.
+    pub fn add(&mut self, event: &MouseWheel) -> i32 {
+        self.lines += match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / PIXELS_PER_LINE,
+        };
+        let steps = self.lines.trunc();
+        self.lines -= steps;
+        steps as i32
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Brush {
+    pub shape: BrushShape,
+    /// Tiles covered on each side of the centre, so 0 paints a single tile.
+    pub radius: i32,
+}
+
+impl Brush {
+    /// Tiles covered by the brush centred on `center`.
+    pub fn points(&self, [x, y]: [i32; 2]) -> impl Iterator<Item = [i32; 2]> {
+        let Brush { shape, radius } = *self;
+        (-radius..=radius)
+            .flat_map(move |dx| (-radius..=radius).map(move |dy| [dx, dy]))
+            .filter(move |[dx, dy]| match shape {
+                BrushShape::Square => true,
+                BrushShape::Circle => dx * dx + dy * dy <= radius * radius,
+            })
+            .map(move |[dx, dy]| [x + dx, y + dy])
+    }
+
+    /// Tiles covered by stamping the brush on every point of `path`, each of them once.
+    pub fn stamp(&self, path: impl IntoIterator<Item = [i32; 2]>) -> BTreeSet<[i32; 2]> {
+        path.into_iter()
+            .flat_map(|point| self.points(point))
+            .collect()
+    }
+}
+
+/// What dragging the mouse in the editor does.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EditorTool {
+    /// Paints under the cursor every frame. `points` paints the whole line from where the
+    /// cursor was the frame before, so that fast strokes have no gaps.
+    #[default]
+    Brush,
+    /// Paints a line from where the drag started to where it ended.
+    Line,
+    /// Paints the outline of the rectangle spanned by the drag.
+    Rect,
+    FilledRect,
+    /// Fills the region connected to the clicked tile, see `GenericTiles::flood_fill`.
+    Bucket,
+}
+
+impl EditorTool {
+    pub fn next(self) -> Self {
+        match self {
+            EditorTool::Brush => EditorTool::Line,
+            EditorTool::Line => EditorTool::Rect,
+            EditorTool::Rect => EditorTool::FilledRect,
+            EditorTool::FilledRect => EditorTool::Bucket,
+            EditorTool::Bucket => EditorTool::Brush,
+        }
+    }
+
+    /// Tiles painted by a drag from `start` to `end`. Filled rectangles are filled without the
+    /// brush, as it would only grow them. The bucket depends on the map, so it paints nothing
+    /// here.
+    pub fn points(self, brush: &Brush, start: [i32; 2], end: [i32; 2]) -> BTreeSet<[i32; 2]> {
+        match self {
+            EditorTool::Brush | EditorTool::Line => brush.stamp(line(start, end)),
+            EditorTool::Rect => brush.stamp(rect(start, end, false)),
+            EditorTool::FilledRect => rect(start, end, true).into_iter().collect(),
+            EditorTool::Bucket => BTreeSet::new(),
+        }
+    }
+}
+
+/// Tiles on the line from `a` to `b`, both included, with no gaps between them.
+pub fn line(a: [i32; 2], b: [i32; 2]) -> Vec<[i32; 2]> {
+    let [mut x, mut y] = a;
+    let dx = (b[0] - a[0]).abs();
+    let dy = -(b[1] - a[1]).abs();
+    let step_x = (b[0] - a[0]).signum();
+    let step_y = (b[1] - a[1]).signum();
+    let mut error = dx + dy;
+
+    let mut points = vec![[x, y]];
+    while [x, y] != b {
+        let double_error = 2 * error;
+        if double_error >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if double_error <= dx {
+            error += dx;
+            y += step_y;
+        }
+        points.push([x, y]);
+    }
+    points
+}
+
+/// Tiles of the rectangle with corners `a` and `b`, or only of its outline.
+pub fn rect(a: [i32; 2], b: [i32; 2], filled: bool) -> Vec<[i32; 2]> {
+    let [min_x, max_x] = [a[0].min(b[0]), a[0].max(b[0])];
+    let [min_y, max_y] = [a[1].min(b[1]), a[1].max(b[1])];
+    (min_x..=max_x)
+        .flat_map(|x| (min_y..=max_y).map(move |y| [x, y]))
+        .filter(|&[x, y]| filled || x == min_x || x == max_x || y == min_y || y == max_y)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn brush_shapes() {
+        let square = Brush {
+            shape: BrushShape::Square,
+            radius: 1,
+        };
+        let circle = Brush {
+            shape: BrushShape::Circle,
+            radius: 1,
+        };
+        assert_eq!(square.points([5, 5]).count(), 9);
+        assert_eq!(circle.points([5, 5]).count(), 5);
+    }
+
+    #[test]
+    fn scroll_steps() {
+        let event = |unit, x, y| MouseWheel { unit, x, y };
+        let mut scroll = ScrollSteps::default();
+        assert_eq!(scroll.add(&event(MouseScrollUnit::Line, 1.0, 0.0)), 0);
+        assert_eq!(scroll.add(&event(MouseScrollUnit::Line, 0.0, -1.0)), -1);
+
+        let pixels = event(MouseScrollUnit::Pixel, 0.0, PIXELS_PER_LINE / 4.0);
+        let steps = (0..8).map(|_| scroll.add(&pixels)).collect::<Vec<_>>();
+        assert_eq!(steps, [0, 0, 0, 1, 0, 0, 0, 1]);
+        assert_eq!(
+            Brush::default().points([5, 5]).collect::<Vec<_>>(),
+            [[5, 5]]
+        );
+    }
+
+    #[test]
+    fn lines_have_no_gaps() {
+        for end in [[7, 3], [-2, 9], [-6, -6], [0, -4], [5, 0]] {
+            let points = line([1, 1], end);
+            assert_eq!(points.first(), Some(&[1, 1]));
+            assert_eq!(points.last(), Some(&end));
+            for pair in points.windows(2) {
+                let step = [pair[1][0] - pair[0][0], pair[1][1] - pair[0][1]];
+                assert!(step[0].abs() <= 1 && step[1].abs() <= 1, "{:?}", pair);
+            }
+        }
+
+        // the brush covers the way the cursor moved between two frames
+        let stroke = EditorTool::Brush.points(&Brush::default(), [0, 0], [3, -1]);
+        assert_eq!(stroke.len(), 4);
+    }
+
+    #[test]
+    fn rect_outline_and_fill() {
+        assert_eq!(rect([3, 2], [0, 0], false).len(), 10);
+        assert_eq!(rect([3, 2], [0, 0], true).len(), 12);
+    }
+}
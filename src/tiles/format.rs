@@ -0,0 +1,465 @@
+use bevy::prelude::Vec4;
+use ndarray::Array2;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use super::generic_tiles::*;
+use super::liquid::LiquidTile;
+use super::liquid_feature::{LiquidFeature, LiquidFeatures};
+use super::liquid_kind::LiquidKind;
+use super::OptTileIndex;
+
+pub const MAGIC: [u8; 4] = *b"TWTR";
+pub const FORMAT_VERSION: u32 = 3;
+
+/// Oldest version `read_world` still accepts. Version 1 had no liquid kinds, version 2 had no
+/// liquid features.
+pub const MIN_FORMAT_VERSION: u32 = 1;
+
+/// Solid and liquid layers of a single world and the liquid features, in the order they are
+/// stored.
+pub type WorldLayers = (
+    GenericTiles<i32, OptTileIndex>,
+    GenericTiles<i32, LiquidTile>,
+    LiquidFeatures,
+);
+
+#[derive(Debug)]
+pub enum FormatError {
+    Io(io::Error),
+    Truncated,
+    BadMagic([u8; 4]),
+    UnsupportedVersion(u32),
+    InvalidChunkSize(u32),
+    ChunkSizeMismatch { solid: usize, liquid: usize },
+    CoordOutOfRange(i64),
+    DuplicateChunk([i64; 2]),
+    DuplicateFeature([i32; 2]),
+    InvalidFeature(u8),
+    TrailingData,
+}
+
+impl std::fmt::Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatError::Io(err) => write!(f, "io error: {}", err),
+            FormatError::Truncated => write!(f, "file is truncated"),
+            FormatError::BadMagic(magic) => write!(f, "not a tile world file (magic {:?})", magic),
+            FormatError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported format version {} (expected {} to {})",
+                version, MIN_FORMAT_VERSION, FORMAT_VERSION
+            ),
+            FormatError::InvalidChunkSize(size) => write!(f, "invalid chunk size {}", size),
+            FormatError::ChunkSizeMismatch { solid, liquid } => write!(
+                f,
+                "solid and liquid layers have different chunk sizes ({} and {})",
+                solid, liquid
+            ),
+            FormatError::CoordOutOfRange(coord) => {
+                write!(
+                    f,
+                    "chunk coordinate {} does not fit the coordinate type",
+                    coord
+                )
+            }
+            FormatError::DuplicateChunk([x, y]) => {
+                write!(f, "chunk [{}, {}] is stored twice", x, y)
+            }
+            FormatError::DuplicateFeature([x, y]) => {
+                write!(f, "two liquid features at [{}, {}]", x, y)
+            }
+            FormatError::InvalidFeature(tag) => write!(f, "unknown liquid feature {}", tag),
+            FormatError::TrailingData => write!(f, "unexpected data after the last layer"),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+impl From<io::Error> for FormatError {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            FormatError::Truncated
+        } else {
+            FormatError::Io(err)
+        }
+    }
+}
+
+/// Fixed-size binary encoding of a single tile.
+///
+/// Tiles are always written in the latest `FORMAT_VERSION`, `decode` gets the version of the
+/// file being read.
+pub trait TileCodec: Sized {
+    fn encode(&self, writer: &mut impl Write) -> io::Result<()>;
+    fn decode(reader: &mut impl Read, version: u32) -> io::Result<Self>;
+}
+
+impl TileCodec for OptTileIndex {
+    fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
+        let raw = self.get_index().map(|index| index + 1).unwrap_or(0);
+        writer.write_all(&raw.to_le_bytes())
+    }
+
+    fn decode(reader: &mut impl Read, _version: u32) -> io::Result<Self> {
+        let raw = read_u32(reader)?;
+        Ok(match raw {
+            0 => OptTileIndex::empty(),
+            raw => OptTileIndex::from_index(raw - 1),
+        })
+    }
+}
+
+impl TileCodec for LiquidTile {
+    fn encode(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.amount.to_le_bytes())?;
+        for component in self.velocity.to_array() {
+            writer.write_all(&component.to_le_bytes())?;
+        }
+        writer.write_all(&self.kind.0.to_le_bytes())
+    }
+
+    fn decode(reader: &mut impl Read, version: u32) -> io::Result<Self> {
+        let amount = read_f32(reader)?;
+        let mut velocity = [0.0; 4];
+        for component in &mut velocity {
+            *component = read_f32(reader)?;
+        }
+        let kind = if version >= 2 {
+            LiquidKind(read_u16(reader)?)
+        } else {
+            LiquidKind::WATER
+        };
+        Ok(LiquidTile {
+            amount,
+            velocity: Vec4::from(velocity),
+            kind,
+        })
+    }
+}
+
+pub fn write_chunk<T: TileCodec>(
+    writer: &mut impl Write,
+    chunk: &GenericChunk<T>,
+) -> Result<(), FormatError> {
+    for tile in chunk.tiles().iter() {
+        tile.encode(writer)?;
+    }
+    Ok(())
+}
+
+pub fn read_chunk<T: TileCodec>(
+    reader: &mut impl Read,
+    chunk_size: usize,
+    version: u32,
+) -> Result<GenericChunk<T>, FormatError> {
+    let tiles = (0..chunk_size * chunk_size)
+        .map(|_| T::decode(reader, version))
+        .collect::<io::Result<Vec<_>>>()?;
+    let tiles = Array2::from_shape_vec([chunk_size; 2], tiles)
+        .expect("tile count always matches the chunk shape");
+    Ok(GenericChunk::from_tiles(tiles))
+}
+
+/// Writes every chunk of a layer, without the file header.
+///
+/// Chunks are written in sorted coordinate order so identical worlds produce identical files.
+pub fn write_tiles<C: Coord, T: TileCodec + Default>(
+    writer: &mut impl Write,
+    tiles: &GenericTiles<C, T>,
+) -> Result<(), FormatError> {
+    let mut chunks = tiles.indexed_chunks().collect::<Vec<_>>();
+    chunks.sort_by_key(|(coord, _)| **coord);
+
+    writer.write_all(&(chunks.len() as u32).to_le_bytes())?;
+    for ([x, y], chunk) in chunks {
+        writer.write_all(&x.to_i64().to_le_bytes())?;
+        writer.write_all(&y.to_i64().to_le_bytes())?;
+        write_chunk(writer, chunk)?;
+    }
+    Ok(())
+}
+
+pub fn read_tiles<C: Coord, T: TileCodec + Default>(
+    reader: &mut impl Read,
+    chunk_size: usize,
+    version: u32,
+) -> Result<GenericTiles<C, T>, FormatError> {
+    let mut tiles = GenericTiles::new(chunk_size);
+
+    let chunk_count = read_u32(reader)?;
+    for _ in 0..chunk_count {
+        let raw_coord = [read_i64(reader)?, read_i64(reader)?];
+        let coord = [
+            C::from_i64(raw_coord[0]).ok_or(FormatError::CoordOutOfRange(raw_coord[0]))?,
+            C::from_i64(raw_coord[1]).ok_or(FormatError::CoordOutOfRange(raw_coord[1]))?,
+        ];
+        let chunk = read_chunk(reader, chunk_size, version)?;
+        if tiles.insert_chunk(coord, chunk).is_some() {
+            return Err(FormatError::DuplicateChunk(raw_coord));
+        }
+    }
+
+    Ok(tiles)
+}
+
+const SOURCE_TAG: u8 = 0;
+const DRAIN_TAG: u8 = 1;
+
+/// Writes liquid features in sorted coordinate order, as a tag followed by the liquid kind
+/// (always 0 for drains) and the rate.
+pub fn write_features(
+    writer: &mut impl Write,
+    features: &LiquidFeatures,
+) -> Result<(), FormatError> {
+    writer.write_all(&(features.len() as u32).to_le_bytes())?;
+    for ([x, y], feature) in features.iter() {
+        writer.write_all(&x.to_le_bytes())?;
+        writer.write_all(&y.to_le_bytes())?;
+        let (tag, kind, rate) = match *feature {
+            LiquidFeature::Source { liquid, rate } => (SOURCE_TAG, liquid, rate),
+            LiquidFeature::Drain { rate } => (DRAIN_TAG, LiquidKind::default(), rate),
+        };
+        writer.write_all(&[tag])?;
+        writer.write_all(&kind.0.to_le_bytes())?;
+        writer.write_all(&rate.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn read_features(reader: &mut impl Read) -> Result<LiquidFeatures, FormatError> {
+    let mut features = LiquidFeatures::default();
+
+    let count = read_u32(reader)?;
+    for _ in 0..count {
+        let point = [read_i32(reader)?, read_i32(reader)?];
+        let mut tag = [0];
+        reader.read_exact(&mut tag)?;
+        let liquid = LiquidKind(read_u16(reader)?);
+        let rate = read_f32(reader)?;
+        let feature = match tag[0] {
+            SOURCE_TAG => LiquidFeature::Source { liquid, rate },
+            DRAIN_TAG => LiquidFeature::Drain { rate },
+            tag => return Err(FormatError::InvalidFeature(tag)),
+        };
+        if features.insert(point, feature).is_some() {
+            return Err(FormatError::DuplicateFeature(point));
+        }
+    }
+
+    Ok(features)
+}
+
+pub fn write_world(
+    writer: &mut impl Write,
+    solid: &GenericTiles<i32, OptTileIndex>,
+    liquid: &GenericTiles<i32, LiquidTile>,
+    features: &LiquidFeatures,
+) -> Result<(), FormatError> {
+    if solid.chunk_size() != liquid.chunk_size() {
+        return Err(FormatError::ChunkSizeMismatch {
+            solid: solid.chunk_size(),
+            liquid: liquid.chunk_size(),
+        });
+    }
+
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    writer.write_all(&(solid.chunk_size() as u32).to_le_bytes())?;
+    write_tiles(writer, solid)?;
+    write_tiles(writer, liquid)?;
+    write_features(writer, features)?;
+    Ok(())
+}
+
+pub fn read_world(reader: &mut impl Read) -> Result<WorldLayers, FormatError> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(FormatError::BadMagic(magic));
+    }
+
+    let version = read_u32(reader)?;
+    if !(MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&version) {
+        return Err(FormatError::UnsupportedVersion(version));
+    }
+
+    let chunk_size = read_u32(reader)?;
+    if chunk_size == 0 {
+        return Err(FormatError::InvalidChunkSize(chunk_size));
+    }
+    let chunk_size = chunk_size as usize;
+
+    let solid = read_tiles(reader, chunk_size, version)?;
+    let liquid = read_tiles(reader, chunk_size, version)?;
+    let features = if version >= 3 {
+        read_features(reader)?
+    } else {
+        LiquidFeatures::default()
+    };
+
+    if reader.read(&mut [0])? != 0 {
+        return Err(FormatError::TrailingData);
+    }
+
+    Ok((solid, liquid, features))
+}
+
+pub fn save_world_file(
+    path: impl AsRef<Path>,
+    solid: &GenericTiles<i32, OptTileIndex>,
+    liquid: &GenericTiles<i32, LiquidTile>,
+    features: &LiquidFeatures,
+) -> Result<(), FormatError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_world(&mut writer, solid, liquid, features)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn load_world_file(path: impl AsRef<Path>) -> Result<WorldLayers, FormatError> {
+    read_world(&mut BufReader::new(File::open(path)?))
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u16(reader: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_i32(reader: &mut impl Read) -> io::Result<i32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(i32::from_le_bytes(bytes))
+}
+
+fn read_i64(reader: &mut impl Read) -> io::Result<i64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(i64::from_le_bytes(bytes))
+}
+
+fn read_f32(reader: &mut impl Read) -> io::Result<f32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample_world() -> WorldLayers {
+        let mut solid = GenericTiles::new(4);
+        solid.set(&[0, 0], OptTileIndex::from_index(0));
+        solid.set(&[-5, 3], OptTileIndex::from_index(1));
+
+        let mut liquid = GenericTiles::new(4);
+        liquid.set(
+            &[2, -7],
+            LiquidTile {
+                amount: 0.75,
+                velocity: Vec4::new(0.1, 0.2, 0.3, 0.4),
+                kind: LiquidKind::OIL,
+            },
+        );
+
+        let mut features = LiquidFeatures::default();
+        features.insert(
+            [1, -7],
+            LiquidFeature::Source {
+                liquid: LiquidKind::LAVA,
+                rate: 0.125,
+            },
+        );
+        features.insert([-3, 2], LiquidFeature::Drain { rate: 0.5 });
+
+        (solid, liquid, features)
+    }
+
+    fn encode((solid, liquid, features): &WorldLayers) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_world(&mut bytes, solid, liquid, features).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let world = sample_world();
+        let (solid, _, features) = &world;
+        let bytes = encode(&world);
+
+        let loaded = read_world(&mut bytes.as_slice()).unwrap();
+        let (loaded_solid, loaded_liquid, loaded_features) = &loaded;
+        assert_eq!(loaded_solid.chunk_size(), 4);
+        assert_eq!(loaded_solid.chunks().len(), solid.chunks().len());
+        for (coord, tile) in solid.indexed_tiles() {
+            assert!(loaded_solid.get(&coord) == Some(tile));
+        }
+
+        let tile = loaded_liquid.get(&[2, -7]).unwrap();
+        assert_eq!(tile.amount, 0.75);
+        assert_eq!(tile.velocity, Vec4::new(0.1, 0.2, 0.3, 0.4));
+        assert_eq!(tile.kind, LiquidKind::OIL);
+        assert!(loaded_features.iter().eq(features.iter()));
+        assert_eq!(bytes, encode(&loaded));
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = encode(&sample_world());
+
+        for len in [0, 3, 10, bytes.len() - 1] {
+            assert!(matches!(
+                read_world(&mut &bytes[..len]),
+                Err(FormatError::Truncated)
+            ));
+        }
+    }
+
+    #[test]
+    fn version_1_liquid_is_water() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&[0; 16]);
+        for value in [0.5f32, 0.0, 0.0, 0.0, 0.0] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let (_, liquid, features) = read_world(&mut bytes.as_slice()).unwrap();
+        assert!(features.is_empty());
+        let tile = liquid.get(&[0, 0]).unwrap();
+        assert_eq!(tile.amount, 0.5);
+        assert_eq!(tile.kind, LiquidKind::WATER);
+    }
+
+    #[test]
+    fn mismatched_header() {
+        let mut bytes = encode(&sample_world());
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert!(matches!(
+            read_world(&mut bad_magic.as_slice()),
+            Err(FormatError::BadMagic(_))
+        ));
+
+        bytes[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            read_world(&mut bytes.as_slice()),
+            Err(FormatError::UnsupportedVersion(_))
+        ));
+    }
+}
@@ -1,10 +1,26 @@
 use ndarray::prelude::*;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 
-#[derive(Default, Clone)]
+pub use coord::Coord;
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: Deserialize<'de>"))]
 pub struct GenericChunk<T> {
+    #[serde(with = "columns")]
     tiles: Array2<T>,
+    #[serde(skip)]
     modified: Vec<[usize; 2]>,
+    #[serde(skip)]
+    activity: ChunkActivity,
+}
+
+/// Whether a chunk is being simulated. Chunks start awake, fall asleep after staying quiet
+/// for a while and are only woken up explicitly.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ChunkActivity {
+    asleep: bool,
+    quiet_steps: u32,
 }
 
 impl<T: Default> GenericChunk<T> {
@@ -12,6 +28,7 @@ impl<T: Default> GenericChunk<T> {
         GenericChunk {
             tiles: Array2::from_shape_fn([size; 2], |_| Default::default()),
             modified: Vec::new(),
+            activity: ChunkActivity::default(),
         }
     }
 }
@@ -34,18 +51,56 @@ impl<T> GenericChunk<T> {
     pub fn tiles(&self) -> &Array2<T> {
         &self.tiles
     }
+
+    pub fn from_tiles(tiles: Array2<T>) -> Self {
+        GenericChunk {
+            tiles,
+            modified: Vec::new(),
+            activity: ChunkActivity::default(),
+        }
+    }
+
+    pub fn tile_mut(&mut self, inner_coord: [usize; 2]) -> &mut T {
+        self.modified.push(inner_coord);
+        &mut self.tiles[inner_coord]
+    }
+
+    pub fn is_asleep(&self) -> bool {
+        self.activity.asleep
+    }
+
+    pub fn wake(&mut self) {
+        self.activity = ChunkActivity::default();
+    }
+
+    /// Counts a step in which the chunk was `active` or not. The chunk falls asleep once
+    /// it has been inactive for `sleep_after` steps in a row.
+    pub fn record_activity(&mut self, active: bool, sleep_after: u32) {
+        if active {
+            self.activity.quiet_steps = 0;
+        } else {
+            self.activity.quiet_steps += 1;
+            if self.activity.quiet_steps >= sleep_after {
+                self.activity.asleep = true;
+            }
+        }
+    }
 }
 
-#[derive(Default, Clone)]
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "C: Coord + Serialize, T: Serialize",
+    deserialize = "C: Coord + Deserialize<'de>, T: Deserialize<'de>"
+))]
 pub struct GenericTiles<C, T> {
     chunk_size: usize,
+    #[serde(with = "sorted_chunks")]
     chunks: HashMap<[C; 2], GenericChunk<T>>,
+    #[serde(skip)]
     modified: Vec<[C; 2]>,
 }
 
-type C = i32;
-
-impl<T: Default> GenericTiles<i32, T> {
+impl<C: Coord, T: Default> GenericTiles<C, T> {
     pub fn new(chunk_size: usize) -> Self {
         GenericTiles {
             chunk_size,
@@ -69,6 +124,45 @@ impl<T: Default> GenericTiles<i32, T> {
             })
     }
 
+    /// Same as `indexed_tiles`, but skips sleeping chunks.
+    pub fn indexed_awake_tiles<'a>(&'a self) -> impl Iterator<Item = ([C; 2], &T)> + 'a {
+        self.chunks
+            .iter()
+            .filter(|(_, chunk)| !chunk.is_asleep())
+            .flat_map(|(chunk_coord, chunk)| {
+                chunk.indexed_tiles().zip(std::iter::repeat(chunk_coord))
+            })
+            .map(move |((inner_coord, tile), chunk_coord)| {
+                (self.combine_coord(chunk_coord, &inner_coord), tile)
+            })
+    }
+
+    pub fn awake_chunks(&self) -> impl Iterator<Item = [C; 2]> + '_ {
+        self.chunks
+            .iter()
+            .filter(|(_, chunk)| !chunk.is_asleep())
+            .map(|(coord, _)| *coord)
+    }
+
+    pub fn chunk_mut(&mut self, coord: &[C; 2]) -> Option<&mut GenericChunk<T>> {
+        self.chunks.get_mut(coord)
+    }
+
+    /// Wakes the chunk holding `point` and the eight chunks around it.
+    pub fn wake_around(&mut self, point: &[C; 2]) {
+        let chunk_coord = self.point_to_chunk_coord(point);
+        self.wake_neighbourhood(&chunk_coord);
+    }
+
+    /// Wakes the chunk at `chunk_coord` and the eight chunks around it.
+    pub fn wake_neighbourhood(&mut self, chunk_coord: &[C; 2]) {
+        for neighbour in coord::neighbourhood(chunk_coord) {
+            if let Some(chunk) = self.chunks.get_mut(&neighbour) {
+                chunk.wake();
+            }
+        }
+    }
+
     pub fn indexed_modified_tiles<'a>(&'a self) -> impl Iterator<Item = ([C; 2], &T)> + 'a {
         self.modified_chunks()
             .iter()
@@ -108,6 +202,14 @@ impl<T: Default> GenericTiles<i32, T> {
         }
     }
 
+    /// Marks every tile as modified, e.g. after the whole layer was replaced.
+    pub fn mark_all_modified(&mut self) {
+        for (chunk_coord, chunk) in &mut self.chunks {
+            self.modified.push(*chunk_coord);
+            chunk.modified = chunk.indexed_tiles().map(|(coord, _)| coord).collect();
+        }
+    }
+
     pub fn clear_modified(&mut self) {
         for chunk_coord in &self.modified {
             self.chunks.get_mut(chunk_coord).map(|c| c.clear_modified());
@@ -127,6 +229,23 @@ impl<T: Default> GenericTiles<i32, T> {
         &self.chunks
     }
 
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    pub fn insert_chunk(
+        &mut self,
+        coord: [C; 2],
+        chunk: GenericChunk<T>,
+    ) -> Option<GenericChunk<T>> {
+        self.modified.push(coord);
+        self.chunks.insert(coord, chunk)
+    }
+
+    pub fn take_chunk(&mut self, coord: &[C; 2]) -> Option<GenericChunk<T>> {
+        self.chunks.remove(coord)
+    }
+
     pub fn set(&mut self, point: &[C; 2], tile: T) {
         let chunk_coord = self.point_to_chunk_coord(point);
         let inner_coord = self.point_to_inner_coord(point);
@@ -164,6 +283,15 @@ impl<T: Default> GenericTiles<i32, T> {
         &mut chunk.tiles[inner_coord]
     }
 
+    /// Marks the tile at `point` as modified without changing it, creating its chunk if there
+    /// is none, so it is drawn again.
+    pub fn mark_modified(&mut self, point: &[C; 2]) {
+        let (chunk_coord, inner_coord) = self.split_coord(point);
+        self.get_chunk_or_create(chunk_coord)
+            .modified
+            .push(inner_coord);
+    }
+
     pub fn point_to_chunk_coord(&self, point: &[C; 2]) -> [C; 2] {
         coord::point_to_chunk_coord(self.chunk_size, point)
     }
@@ -187,39 +315,180 @@ impl<T: Default> GenericTiles<i32, T> {
     pub fn split_coord(&self, point: &[C; 2]) -> ([C; 2], [usize; 2]) {
         coord::split_coord(self.chunk_size, point)
     }
+
+    /// Points connected to `start` through their four neighbours whose tiles match
+    /// `predicate`, in the order they were reached. Points without a chunk are matched as the
+    /// default tile. Returns `None` when the region grows past `bounds.max_area`.
+    pub fn flood_fill(
+        &self,
+        start: [C; 2],
+        bounds: FillBounds<C>,
+        mut predicate: impl FnMut([C; 2], &T) -> bool,
+    ) -> Option<Vec<[C; 2]>> {
+        let default = T::default();
+        let mut matches = |point: [C; 2]| {
+            bounds.contains(&point) && predicate(point, self.get(&point).unwrap_or(&default))
+        };
+
+        let mut region = Vec::new();
+        if !matches(start) {
+            return Some(region);
+        }
+        let mut visited = HashSet::from([start]);
+        let mut open = vec![start];
+        let one = C::from_usize(1);
+        while let Some(point @ [x, y]) = open.pop() {
+            region.push(point);
+            if region.len() > bounds.max_area {
+                return None;
+            }
+            for next in [[x - one, y], [x + one, y], [x, y - one], [x, y + one]] {
+                if !visited.contains(&next) && matches(next) {
+                    visited.insert(next);
+                    open.push(next);
+                }
+            }
+        }
+        Some(region)
+    }
+}
+
+impl<C: Coord, T: Default + Clone> GenericTiles<C, T> {
+    /// A copy of only the chunks at `chunk_coords`, for reading a part of a large world
+    /// without copying all of it. Missing chunks stay missing.
+    pub fn clone_chunks(&self, chunk_coords: &[[C; 2]]) -> Self {
+        GenericTiles {
+            chunk_size: self.chunk_size,
+            chunks: chunk_coords
+                .iter()
+                .filter_map(|coord| Some((*coord, self.chunks.get(coord)?.clone())))
+                .collect(),
+            modified: Vec::new(),
+        }
+    }
+}
+
+/// Limits of `GenericTiles::flood_fill`, so that it cannot run away into an unbounded world.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FillBounds<C> {
+    /// Largest number of tiles the region may have.
+    pub max_area: usize,
+    /// Lowest and highest corner of the rectangle the region is clipped to, both included.
+    pub rect: Option<[[C; 2]; 2]>,
+}
+
+impl<C: Coord> FillBounds<C> {
+    pub fn contains(&self, &[x, y]: &[C; 2]) -> bool {
+        match self.rect {
+            Some([[min_x, min_y], [max_x, max_y]]) => {
+                min_x <= x && x <= max_x && min_y <= y && y <= max_y
+            }
+            None => true,
+        }
+    }
 }
 
 pub mod coord {
-    pub type C = i32;
+    use std::fmt::Debug;
+    use std::hash::Hash;
+    use std::ops::{Add, Mul, Sub};
+
+    /// Integer type usable as a tile coordinate.
+    pub trait Coord:
+        Copy
+        + Default
+        + Debug
+        + Eq
+        + Ord
+        + Hash
+        + Send
+        + Sync
+        + 'static
+        + Add<Output = Self>
+        + Sub<Output = Self>
+        + Mul<Output = Self>
+    {
+        fn from_usize(value: usize) -> Self;
+        fn to_usize(self) -> usize;
+        fn from_i64(value: i64) -> Option<Self>;
+        fn to_i64(self) -> i64;
+        fn div_euclid(self, rhs: Self) -> Self;
+        fn rem_euclid(self, rhs: Self) -> Self;
+    }
+
+    macro_rules! impl_coord {
+        ($($t:ty),*) => {
+            $(
+                impl Coord for $t {
+                    fn from_usize(value: usize) -> Self {
+                        value as $t
+                    }
+
+                    fn to_usize(self) -> usize {
+                        self as usize
+                    }
+
+                    fn from_i64(value: i64) -> Option<Self> {
+                        <$t>::try_from(value).ok()
+                    }
+
+                    fn to_i64(self) -> i64 {
+                        self as i64
+                    }
 
-    pub fn point_to_chunk_coord(chunk_size: usize, point: &[C; 2]) -> [C; 2] {
-        map(point, |c| c.div_euclid(chunk_size as C))
+                    fn div_euclid(self, rhs: Self) -> Self {
+                        <$t>::div_euclid(self, rhs)
+                    }
+
+                    fn rem_euclid(self, rhs: Self) -> Self {
+                        <$t>::rem_euclid(self, rhs)
+                    }
+                }
+            )*
+        };
     }
 
-    pub fn point_to_inner_coord(chunk_size: usize, point: &[C; 2]) -> [usize; 2] {
-        map(point, |c| c.rem_euclid(chunk_size as C) as usize)
+    impl_coord!(i16, i32, i64);
+
+    pub fn point_to_chunk_coord<C: Coord>(chunk_size: usize, point: &[C; 2]) -> [C; 2] {
+        map(point, |c| c.div_euclid(C::from_usize(chunk_size)))
     }
 
-    pub fn chunk_coord_to_corner(chunk_size: usize, chunk_coord: &[C; 2]) -> [C; 2] {
-        map(chunk_coord, |c| *c * chunk_size as C)
+    pub fn point_to_inner_coord<C: Coord>(chunk_size: usize, point: &[C; 2]) -> [usize; 2] {
+        map(point, |c| {
+            c.rem_euclid(C::from_usize(chunk_size)).to_usize()
+        })
     }
 
-    pub fn combine_coord(
+    pub fn chunk_coord_to_corner<C: Coord>(chunk_size: usize, chunk_coord: &[C; 2]) -> [C; 2] {
+        map(chunk_coord, |c| *c * C::from_usize(chunk_size))
+    }
+
+    pub fn combine_coord<C: Coord>(
         chunk_size: usize,
         chunk_coord: &[C; 2],
         inner_coord: &[usize; 2],
     ) -> [C; 2] {
         zip_map(chunk_coord, inner_coord, |c, i| {
-            c * chunk_size as C + *i as C
+            *c * C::from_usize(chunk_size) + C::from_usize(*i)
         })
     }
 
-    pub fn split_coord(chunk_size: usize, point: &[C; 2]) -> ([C; 2], [usize; 2]) {
+    pub fn split_coord<C: Coord>(chunk_size: usize, point: &[C; 2]) -> ([C; 2], [usize; 2]) {
         let chunk_coord = point_to_chunk_coord(chunk_size, point);
         let inner_coord = point_to_inner_coord(chunk_size, point);
         (chunk_coord, inner_coord)
     }
 
+    /// The chunk coordinate itself followed by the eight around it.
+    pub fn neighbourhood<C: Coord>(&[x, y]: &[C; 2]) -> impl Iterator<Item = [C; 2]> {
+        let one = C::from_usize(1);
+        let zero = C::from_usize(0);
+        [zero, zero - one, one]
+            .into_iter()
+            .flat_map(move |dx| [zero, zero - one, one].map(|dy| [x + dx, y + dy]))
+    }
+
     fn map<T, U>([x, y]: &[T; 2], f: impl Fn(&T) -> U) -> [U; 2] {
         [f(x), f(y)]
     }
@@ -229,61 +498,185 @@ pub mod coord {
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+/// Stores a chunk as a list of columns so text formats keep one column per entry.
+mod columns {
+    use ndarray::{Array2, ArrayView1, Axis};
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-    #[test]
-    fn point_to_chunk_coord() {
-        let tiles = GenericTiles::<i32, ()>::new(16);
-        assert_eq!(tiles.point_to_chunk_coord(&[0, 0]), [0, 0]);
-        assert_eq!(tiles.point_to_chunk_coord(&[10, 10]), [0, 0]);
-        assert_eq!(tiles.point_to_chunk_coord(&[16, 16]), [1, 1]);
-        assert_eq!(tiles.point_to_chunk_coord(&[-5, -5]), [-1, -1]);
-        assert_eq!(tiles.point_to_chunk_coord(&[-15, -15]), [-1, -1]);
-        assert_eq!(tiles.point_to_chunk_coord(&[-16, -16]), [-1, -1]);
-        assert_eq!(tiles.point_to_chunk_coord(&[-17, -17]), [-2, -2]);
+    struct Column<'a, T>(ArrayView1<'a, T>);
+
+    impl<T: Serialize> Serialize for Column<'_, T> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(self.0.iter())
+        }
     }
 
-    #[test]
-    fn point_to_inner_coord() {
-        let tiles = GenericTiles::<i32, ()>::new(16);
-        assert_eq!(tiles.point_to_inner_coord(&[0, 0]), [0, 0]);
-        assert_eq!(tiles.point_to_inner_coord(&[10, 10]), [10, 10]);
-        assert_eq!(tiles.point_to_inner_coord(&[16, 16]), [0, 0]);
-        assert_eq!(tiles.point_to_inner_coord(&[-1, -1]), [15, 15]);
-        assert_eq!(tiles.point_to_inner_coord(&[-2, -2]), [14, 14]);
-        assert_eq!(tiles.point_to_inner_coord(&[-16, -16]), [0, 0]);
-        assert_eq!(tiles.point_to_inner_coord(&[-17, -17]), [15, 15]);
+    pub fn serialize<T: Serialize, S: Serializer>(
+        tiles: &Array2<T>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(tiles.axis_iter(Axis(0)).map(Column))
     }
 
-    #[test]
-    fn chunk_coord_to_corner() {
-        let tiles = GenericTiles::<i32, ()>::new(16);
-        assert_eq!(tiles.chunk_coord_to_corner(&[0, 0]), [0, 0]);
-        assert_eq!(tiles.chunk_coord_to_corner(&[1, 1]), [16, 16]);
-        assert_eq!(tiles.chunk_coord_to_corner(&[-1, -1]), [-16, -16]);
-        assert_eq!(tiles.chunk_coord_to_corner(&[-2, -2]), [-32, -32]);
+    pub fn deserialize<'de, T: Deserialize<'de>, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Array2<T>, D::Error> {
+        let columns = Vec::<Vec<T>>::deserialize(deserializer)?;
+        let size = columns.len();
+        if columns.iter().any(|column| column.len() != size) {
+            return Err(D::Error::custom("chunk is not square"));
+        }
+
+        let tiles = columns.into_iter().flatten().collect();
+        Array2::from_shape_vec([size; 2], tiles).map_err(D::Error::custom)
+    }
+}
+
+/// Stores chunks as a list sorted by chunk coordinate, so the output does not depend on
+/// `HashMap` order.
+mod sorted_chunks {
+    use super::{Coord, GenericChunk};
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+
+    pub fn serialize<C, T, S>(
+        chunks: &HashMap<[C; 2], GenericChunk<T>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        C: Coord + Serialize,
+        T: Serialize,
+        S: Serializer,
+    {
+        let mut sorted = chunks.iter().collect::<Vec<_>>();
+        sorted.sort_by_key(|(coord, _)| **coord);
+        serializer.collect_seq(sorted)
+    }
+
+    pub fn deserialize<'de, C, T, D>(
+        deserializer: D,
+    ) -> Result<HashMap<[C; 2], GenericChunk<T>>, D::Error>
+    where
+        C: Coord + Deserialize<'de>,
+        T: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let list = Vec::<([C; 2], GenericChunk<T>)>::deserialize(deserializer)?;
+        let mut chunks = HashMap::with_capacity(list.len());
+        for (coord, chunk) in list {
+            if chunks.insert(coord, chunk).is_some() {
+                return Err(D::Error::custom(format!(
+                    "chunk {:?} is stored twice",
+                    coord
+                )));
+            }
+        }
+        Ok(chunks)
     }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    macro_rules! coord_tests {
+        ($($name:ident: $c:ty),*) => {
+            $(
+                mod $name {
+                    use super::*;
+
+                    #[test]
+                    fn point_to_chunk_coord() {
+                        let tiles = GenericTiles::<$c, ()>::new(16);
+                        assert_eq!(tiles.point_to_chunk_coord(&[0, 0]), [0, 0]);
+                        assert_eq!(tiles.point_to_chunk_coord(&[10, 10]), [0, 0]);
+                        assert_eq!(tiles.point_to_chunk_coord(&[16, 16]), [1, 1]);
+                        assert_eq!(tiles.point_to_chunk_coord(&[-5, -5]), [-1, -1]);
+                        assert_eq!(tiles.point_to_chunk_coord(&[-15, -15]), [-1, -1]);
+                        assert_eq!(tiles.point_to_chunk_coord(&[-16, -16]), [-1, -1]);
+                        assert_eq!(tiles.point_to_chunk_coord(&[-17, -17]), [-2, -2]);
+                    }
+
+                    #[test]
+                    fn point_to_inner_coord() {
+                        let tiles = GenericTiles::<$c, ()>::new(16);
+                        assert_eq!(tiles.point_to_inner_coord(&[0, 0]), [0, 0]);
+                        assert_eq!(tiles.point_to_inner_coord(&[10, 10]), [10, 10]);
+                        assert_eq!(tiles.point_to_inner_coord(&[16, 16]), [0, 0]);
+                        assert_eq!(tiles.point_to_inner_coord(&[-1, -1]), [15, 15]);
+                        assert_eq!(tiles.point_to_inner_coord(&[-2, -2]), [14, 14]);
+                        assert_eq!(tiles.point_to_inner_coord(&[-16, -16]), [0, 0]);
+                        assert_eq!(tiles.point_to_inner_coord(&[-17, -17]), [15, 15]);
+                    }
+
+                    #[test]
+                    fn chunk_coord_to_corner() {
+                        let tiles = GenericTiles::<$c, ()>::new(16);
+                        assert_eq!(tiles.chunk_coord_to_corner(&[0, 0]), [0, 0]);
+                        assert_eq!(tiles.chunk_coord_to_corner(&[1, 1]), [16, 16]);
+                        assert_eq!(tiles.chunk_coord_to_corner(&[-1, -1]), [-16, -16]);
+                        assert_eq!(tiles.chunk_coord_to_corner(&[-2, -2]), [-32, -32]);
+                    }
+
+                    #[test]
+                    fn combine_coord() {
+                        let tiles = GenericTiles::<$c, ()>::new(16);
+
+                        let points = [
+                            [0, 0],
+                            [10, 10],
+                            [16, 16],
+                            [-5, -5],
+                            [-15, -15],
+                            [-16, -16],
+                            [-17, -17],
+                        ];
+
+                        for point in &points {
+                            let chunk_coord = tiles.point_to_chunk_coord(point);
+                            let inner_coord = tiles.point_to_inner_coord(point);
+                            assert_eq!(tiles.combine_coord(&chunk_coord, &inner_coord), *point);
+                        }
+                    }
+                }
+            )*
+        };
+    }
+
+    coord_tests!(i16: i16, i32: i32, i64: i64);
 
     #[test]
-    fn combine_coord() {
-        let tiles = GenericTiles::<i32, ()>::new(16);
-
-        let points = [
-            [0, 0],
-            [10, 10],
-            [16, 16],
-            [-5, -5],
-            [-15, -15],
-            [-16, -16],
-            [-17, -17],
-        ];
-
-        for point in &points {
-            let chunk_coord = tiles.point_to_chunk_coord(point);
-            let inner_coord = tiles.point_to_inner_coord(point);
-            assert_eq!(tiles.combine_coord(&chunk_coord, &inner_coord), *point);
+    fn flood_fill_is_bounded() {
+        // a 3x2 room with walls around it and a gap at [4, 1]
+        let mut tiles = GenericTiles::<i32, bool>::new(4);
+        for x in 0..=4 {
+            tiles.set(&[x, 0], true);
+            tiles.set(&[x, 3], true);
+        }
+        for y in 0..=3 {
+            tiles.set(&[0, y], true);
+            tiles.set(&[4, y], true);
         }
+        let open = |_, wall: &bool| !*wall;
+        let area = |max_area| FillBounds {
+            max_area,
+            rect: None,
+        };
+
+        assert_eq!(tiles.flood_fill([2, 1], area(100), open).unwrap().len(), 6);
+        assert_eq!(tiles.flood_fill([0, 0], area(100), open), Some(vec![]));
+
+        tiles.set(&[4, 1], false);
+        assert_eq!(tiles.flood_fill([2, 1], area(100), open), None);
+
+        let clipped = FillBounds {
+            max_area: 100,
+            rect: Some([[0, 0], [5, 5]]),
+        };
+        let region = tiles.flood_fill([2, 1], clipped, open).unwrap();
+        assert!(region.iter().all(|point| clipped.contains(point)));
+        assert!(region.contains(&[5, 5]));
     }
 }
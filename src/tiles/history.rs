@@ -0,0 +1,231 @@
+use bevy::prelude::Entity;
+use std::collections::BTreeMap;
+
+use super::generic_tiles::*;
+use super::liquid::LiquidTile;
+use super::liquid_feature::{LiquidFeature, LiquidFeatures};
+use super::{LiquidTiles, OptTileIndex, SolidTiles};
+
+/// Strokes kept for undo, older ones are dropped.
+const MAX_STROKES: usize = 100;
+
+/// Old and new value of every point changed by a stroke.
+#[derive(Clone)]
+pub struct Changes<T> {
+    changes: BTreeMap<[i32; 2], (T, T)>,
+}
+
+impl<T> Default for Changes<T> {
+    fn default() -> Self {
+        Changes {
+            changes: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Clone> Changes<T> {
+    /// Records that `point` changed from `before` to `after`. A point changed several times
+    /// keeps its first `before`.
+    pub fn record(&mut self, point: [i32; 2], before: T, after: T) {
+        self.changes
+            .entry(point)
+            .and_modify(|(_, last)| *last = after.clone())
+            .or_insert((before, after));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The values before the changes when undoing, or after them when redoing.
+    fn values(&self, redo: bool) -> impl Iterator<Item = (&[i32; 2], &T)> {
+        self.changes
+            .iter()
+            .map(move |(point, (before, after))| (point, if redo { after } else { before }))
+    }
+}
+
+impl<T: Clone + Default> Changes<T> {
+    /// Writes the values back, marking the tiles as modified.
+    pub fn apply_to(&self, tiles: &mut GenericTiles<i32, T>, redo: bool) {
+        for (point, value) in self.values(redo) {
+            tiles.set(point, value.clone());
+        }
+    }
+}
+
+impl Changes<Option<LiquidFeature>> {
+    pub fn apply_to_features(&self, features: &mut LiquidFeatures, redo: bool) {
+        for (point, feature) in self.values(redo) {
+            match feature {
+                Some(feature) => features.insert(*point, *feature),
+                None => features.remove(point),
+            };
+        }
+    }
+}
+
+/// Everything changed on one map while a mouse button or editing key was held.
+#[derive(Clone)]
+pub struct Stroke {
+    pub map: Entity,
+    pub solid: Changes<OptTileIndex>,
+    pub liquid: Changes<LiquidTile>,
+    pub features: Changes<Option<LiquidFeature>>,
+}
+
+impl Stroke {
+    fn new(map: Entity) -> Self {
+        Stroke {
+            map,
+            solid: Changes::default(),
+            liquid: Changes::default(),
+            features: Changes::default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.solid.is_empty() && self.liquid.is_empty() && self.features.is_empty()
+    }
+
+    /// Puts the map back to how it was before the stroke, or after it when redoing. Liquid
+    /// that flowed away from painted tiles in the meantime stays where it is.
+    pub fn apply(&self, solid: &mut SolidTiles, liquid: &mut LiquidTiles, redo: bool) {
+        self.solid.apply_to(&mut solid.tiles, redo);
+        self.liquid.apply_to(&mut liquid.tiles, redo);
+        self.features.apply_to_features(&mut liquid.features, redo);
+
+        let points = self.solid.changes.keys();
+        let points = points.chain(self.liquid.changes.keys());
+        for point in points.chain(self.features.changes.keys()) {
+            // redraws feature markers and gets the liquid around changed walls moving
+            liquid.tiles.mark_modified(point);
+            liquid.tiles.wake_around(point);
+        }
+    }
+}
+
+/// Undo and redo stacks of the tile editor.
+#[derive(Default)]
+pub struct EditHistory {
+    undo: Vec<Stroke>,
+    redo: Vec<Stroke>,
+    stroke: Option<Stroke>,
+}
+
+impl EditHistory {
+    /// The stroke being recorded on `map`, started if there is none. A stroke on another map
+    /// is finished first.
+    pub fn stroke(&mut self, map: Entity) -> &mut Stroke {
+        if self
+            .stroke
+            .as_ref()
+            .map_or(false, |stroke| stroke.map != map)
+        {
+            self.finish_stroke();
+        }
+        self.stroke.get_or_insert_with(|| Stroke::new(map))
+    }
+
+    /// Moves the current stroke onto the undo stack, unless it did not change anything.
+    pub fn finish_stroke(&mut self) {
+        match self.stroke.take() {
+            Some(stroke) if !stroke.is_empty() => {
+                self.undo.push(stroke);
+                if self.undo.len() > MAX_STROKES {
+                    self.undo.remove(0);
+                }
+                self.redo.clear();
+            }
+            _ => {}
+        }
+    }
+
+    /// Drops every stroke on `map`, as they no longer apply once it was cleared or loaded.
+    pub fn forget(&mut self, map: Entity) {
+        self.undo.retain(|stroke| stroke.map != map);
+        self.redo.retain(|stroke| stroke.map != map);
+        if self
+            .stroke
+            .as_ref()
+            .map_or(false, |stroke| stroke.map == map)
+        {
+            self.stroke = None;
+        }
+    }
+
+    /// The stroke to undo, which is moved onto the redo stack.
+    pub fn undo(&mut self) -> Option<&Stroke> {
+        self.finish_stroke();
+        let stroke = self.undo.pop()?;
+        self.redo.push(stroke);
+        self.redo.last()
+    }
+
+    /// The stroke to redo, which is moved back onto the undo stack.
+    pub fn redo(&mut self) -> Option<&Stroke> {
+        self.finish_stroke();
+        let stroke = self.redo.pop()?;
+        self.undo.push(stroke);
+        self.undo.last()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn undo_and_redo_strokes() {
+        let map = Entity::from_raw(0);
+        let mut tiles = GenericTiles::new(4);
+        let mut history = EditHistory::default();
+
+        // one stroke painting over the same tile twice
+        for (point, index) in [([0, 0], 1), ([1, 0], 1), ([0, 0], 2)] {
+            let before = tiles.get_or_default(&point);
+            let after = OptTileIndex::from_index(index);
+            tiles.set(&point, after);
+            history.stroke(map).solid.record(point, before, after);
+        }
+        history.finish_stroke();
+        let painted = [tiles.get_or_default(&[0, 0]), tiles.get_or_default(&[1, 0])];
+
+        history.undo().unwrap().solid.apply_to(&mut tiles, false);
+        assert_eq!(tiles.get(&[0, 0]), Some(&OptTileIndex::empty()));
+        assert_eq!(tiles.get(&[1, 0]), Some(&OptTileIndex::empty()));
+        assert!(history.undo().is_none());
+
+        history.redo().unwrap().solid.apply_to(&mut tiles, true);
+        assert_eq!(
+            [tiles.get_or_default(&[0, 0]), tiles.get_or_default(&[1, 0])],
+            painted
+        );
+        assert!(history.redo().is_none());
+    }
+
+    #[test]
+    fn forget_drops_strokes_of_one_map() {
+        let (cleared, other) = (Entity::from_raw(0), Entity::from_raw(1));
+        let mut history = EditHistory::default();
+        for map in [other, cleared, cleared] {
+            let tile = OptTileIndex::from_index(0);
+            history
+                .stroke(map)
+                .solid
+                .record([0, 0], OptTileIndex::empty(), tile);
+            history.finish_stroke();
+        }
+        history.undo();
+        history
+            .stroke(cleared)
+            .solid
+            .record([1, 0], OptTileIndex::empty(), OptTileIndex::empty());
+
+        history.forget(cleared);
+        assert_eq!(history.undo().map(|stroke| stroke.map), Some(other));
+        assert!(history.undo().is_none());
+        history.redo();
+        assert!(history.redo().is_none());
+    }
+}
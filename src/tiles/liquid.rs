@@ -1,28 +1,52 @@
 use super::generic_tiles::*;
-use super::OptTileIndex;
+use super::liquid_kind::*;
+use super::liquid_settings::{KindParams, LiquidSimSettings};
+use super::tile_type::SolidView;
 use bevy::prelude::Vec4;
+use bevy::tasks::TaskPool;
+use ndarray::Array2;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 
-#[derive(Default, Clone)]
+/// Default of `LiquidSimSettings::empty_threshold`.
+pub const EMPTY_THRESHOLD: f32 = 0.01;
+
+/// How much liquid a tile holds without any pressure on it.
+pub const CAPACITY: f32 = 1.0;
+
+/// How much more liquid a tile can hold for each full tile above it.
+pub const COMPRESSION: f32 = 0.02;
+
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct LiquidTile {
     pub amount: f32,
     pub velocity: Vec4,
+    #[serde(default)]
+    pub kind: LiquidKind,
 }
 
 impl LiquidTile {
     pub fn new(amount: f32) -> Self {
+        Self::with_kind(amount, LiquidKind::WATER)
+    }
+
+    pub fn with_kind(amount: f32, kind: LiquidKind) -> Self {
         LiquidTile {
             amount,
             velocity: Vec4::default(),
+            kind,
         }
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.amount <= 0.01
+    /// Whether the tile holds less than `threshold`, which is
+    /// `LiquidSimSettings::empty_threshold` everywhere outside of tests.
+    pub fn is_empty(&self, threshold: f32) -> bool {
+        self.amount < threshold
     }
 }
 
 #[derive(Debug, Clone, Copy)]
-enum Direction {
+pub(super) enum Direction {
     Right,
     Down,
     Left,
@@ -30,7 +54,7 @@ enum Direction {
 }
 
 impl Direction {
-    fn all() -> [Direction; 4] {
+    pub(super) fn all() -> [Direction; 4] {
         [
             Direction::Right,
             Direction::Down,
@@ -67,6 +91,11 @@ impl Direction {
         }
     }
 
+    pub(super) fn offset_from(self, [x, y]: [i32; 2]) -> [i32; 2] {
+        let [dx, dy] = self.offset();
+        [x + dx, y + dy]
+    }
+
     fn map(mut f: impl FnMut(Direction) -> f32) -> Vec4 {
         let mut vec = Vec4::ZERO;
         for dir in Self::all() {
@@ -112,81 +141,556 @@ impl Direction {
     }
 }
 
+/// How `step` orders its tile updates. `step` defaults to `InPlace`, which runs on a single
+/// thread. The game runs `Deterministic` on the compute task pool, `D` switches between them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LiquidStepMode {
+    /// Tiles are updated in `HashMap` order and see the changes made by tiles updated before them.
+    #[default]
+    InPlace,
+    /// Every tile reads the state from the start of the step and updates are applied chunk by
+    /// chunk, in sorted coordinate order within a chunk, so identical inputs always give
+    /// identical results. Chunks are updated in parallel when `step_on_pool` gets a pool.
+    Deterministic,
+}
+
+/// Liquid volume accounting for a single `step`.
+///
+/// The step itself only moves liquid between tiles, so `delta` is pure floating point error.
+/// `clamped` is the part of it caused by tiles that would have gone below zero. Volumes only
+/// cover the awake chunks and their neighbours, which is everything a step can change.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct LiquidStepReport {
+    pub volume_before: f64,
+    pub volume_after: f64,
+    /// Liquid moved out of tiles below the empty threshold into a fuller neighbour.
+    pub absorbed: f64,
+    /// Number of tiles swapped with the lighter liquid below them.
+    pub swapped: usize,
+    pub clamped: f64,
+    /// Number of chunks simulated in this step. Sleeping chunks are skipped.
+    pub awake_chunks: usize,
+    /// Liquid added by sources before the step. Not part of `delta`.
+    pub emitted: f64,
+    /// Liquid removed by drains before the step. Not part of `delta`.
+    pub drained: f64,
+    /// Liquid used up by the reactions run after the step. Not part of `delta`.
+    pub consumed: f64,
+}
+
+impl LiquidStepReport {
+    pub fn delta(&self) -> f64 {
+        self.volume_after - self.volume_before
+    }
+}
+
+/// Amount and kind of every tile of a chunk, by chunk coordinate, for telling how much a
+/// chunk changed during a step.
+type ChunkSnapshot = ([i32; 2], Array2<(f32, LiquidKind)>);
+
+struct TileUpdate {
+    kind: LiquidKind,
+    velocity: Vec4,
+    outflow: Vec4,
+}
+
 impl GenericTiles<i32, LiquidTile> {
-    pub fn step(&mut self, solid: &GenericTiles<i32, OptTileIndex>, t: f32) {
-        let active_tiles = self.indexed_tiles()
-            .filter(|(_, tile)| tile.amount >= 0.01)
-            .map(|(coord, tile)| (coord, tile.clone()))
+    pub fn step(
+        &mut self,
+        solid: SolidView,
+        kinds: &LiquidKinds,
+        settings: &LiquidSimSettings,
+    ) -> LiquidStepReport {
+        self.step_with_mode(solid, kinds, settings, LiquidStepMode::default())
+    }
+
+    pub fn step_with_mode(
+        &mut self,
+        solid: SolidView,
+        kinds: &LiquidKinds,
+        settings: &LiquidSimSettings,
+        mode: LiquidStepMode,
+    ) -> LiquidStepReport {
+        self.step_on_pool(solid, kinds, settings, mode, None)
+    }
+
+    /// Same as `step_with_mode`, but spreads `LiquidStepMode::Deterministic` steps over `pool`.
+    /// The result is the same as without a pool.
+    pub fn step_on_pool(
+        &mut self,
+        solid: SolidView,
+        kinds: &LiquidKinds,
+        settings: &LiquidSimSettings,
+        mode: LiquidStepMode,
+        pool: Option<&TaskPool>,
+    ) -> LiquidStepReport {
+        let mut awake = self.awake_chunks().collect::<Vec<_>>();
+        awake.sort_unstable();
+        let mut region = awake
+            .iter()
+            .flat_map(coord::neighbourhood)
             .collect::<Vec<_>>();
+        region.sort_unstable();
+        region.dedup();
+
+        let mut report = LiquidStepReport {
+            volume_before: self.region_volume(&region),
+            awake_chunks: awake.len(),
+            ..Default::default()
+        };
+
+        report.absorbed = self.absorb_residue(solid, settings.empty_threshold);
+        let params = settings.params(kinds);
+
+        let before = awake
+            .iter()
+            .map(|coord| (*coord, self.chunk_snapshot(coord)))
+            .collect::<Vec<ChunkSnapshot>>();
 
-        for (coord, tile) in active_tiles {
-            if let Some(tile) = self.get_mut(&coord) {
-                tile.velocity *= 0.9;
-                //tile.velocity = Direction::normalize(tile.velocity);
+        match mode {
+            LiquidStepMode::InPlace => {
+                let active_tiles = self
+                    .indexed_awake_tiles()
+                    .filter(|(_, tile)| tile.amount >= settings.empty_threshold)
+                    .map(|(coord, _)| coord)
+                    .collect::<Vec<_>>();
+                for coord in active_tiles {
+                    let update = self.tile_update(solid, coord, settings, &params);
+                    report.clamped +=
+                        apply_update(self, coord, update, settings.empty_threshold) as f64;
+                }
+            }
+            LiquidStepMode::Deterministic => {
+                // updates only read tiles in and next to the awake chunks
+                let previous = self.clone_chunks(&region);
+                for pass in CHUNK_PASSES {
+                    let chunks = awake
+                        .iter()
+                        .filter(|coord| [coord[0].rem_euclid(2), coord[1].rem_euclid(2)] == pass)
+                        .copied()
+                        .collect::<Vec<_>>();
+                    report.clamped +=
+                        self.update_chunks(&previous, solid, settings, &params, &chunks, pool);
+                }
             }
+        }
 
-            let gradient = Direction::map_offset(coord, |offset_coord, _| {
-                let amount = self.get_or_default(&offset_coord).amount;
-                tile.amount - amount
-            });
+        report.swapped = self.settle_layers(kinds, settings.empty_threshold);
+        self.update_sleep(&before, settings);
 
-            let gravity =
-                Direction::Down.single_component(1.0) + Direction::Up.single_component(-1.0);
-            let force = (gradient + gravity * 0.1).max(Vec4::ZERO);
+        report.volume_after = self.region_volume(&region);
+        report
+    }
 
-            let tile = self.get_or_create(&coord);
-            let acceleration = force * t;
-            tile.velocity += acceleration;
+    /// Updates the awake chunks of one of the `CHUNK_PASSES`, reading neighbours from
+    /// `previous`. Returns the clamped volume.
+    fn update_chunks(
+        &mut self,
+        previous: &Self,
+        solid: SolidView,
+        settings: &LiquidSimSettings,
+        params: &KindParams,
+        chunks: &[[i32; 2]],
+        pool: Option<&TaskPool>,
+    ) -> f64 {
+        let detached = chunks
+            .iter()
+            .filter_map(|coord| Some((*coord, self.take_chunk(coord)?)))
+            .collect::<Vec<_>>();
 
-            Direction::for_each_offset(coord, |offset_coord, dir| {
-                if solid.get(&offset_coord).and_then(|t| t.get_index()).is_some() {
-                    *dir.index_vec_mut(&mut tile.velocity) = 0.0;
+        let rest = &*self;
+        let update = |(coord, chunk)| {
+            DetachedChunk {
+                coord,
+                chunk,
+                rest,
+                outside: HashMap::new(),
+            }
+            .update(previous, solid, settings, params)
+        };
+        let results = match pool {
+            Some(pool) => pool.scope(|scope| {
+                for item in detached {
+                    scope.spawn(async move { update(item) });
                 }
-            });
+            }),
+            None => detached.into_iter().map(update).collect(),
+        };
 
-            let tile = tile.clone();
+        let mut clamped = 0.0;
+        for (coord, chunk, outside, chunk_clamped) in results {
+            self.insert_chunk(coord, chunk);
+            for (point, tile) in outside {
+                self.set(&point, tile);
+            }
+            clamped += chunk_clamped;
+        }
+        clamped
+    }
+
+    fn chunk_snapshot(&self, chunk_coord: &[i32; 2]) -> Array2<(f32, LiquidKind)> {
+        self.chunks()[chunk_coord]
+            .tiles()
+            .map(|tile| (tile.amount, tile.kind))
+    }
+
+    /// Puts chunks whose tiles changed by less than `LiquidSimSettings::sleep_threshold` in
+    /// total for long enough to sleep, and wakes the neighbours of every chunk that changed
+    /// by more. Liquid sloshing back and forth between two tiles does not count as change.
+    fn update_sleep(&mut self, before: &[ChunkSnapshot], settings: &LiquidSimSettings) {
+        for (chunk_coord, before) in before {
+            let after = self.chunk_snapshot(chunk_coord);
+            let change = before
+                .iter()
+                .zip(&after)
+                .map(|((amount_a, kind_a), (amount_b, kind_b))| {
+                    if kind_a == kind_b {
+                        (amount_a - amount_b).abs()
+                    } else {
+                        amount_a + amount_b
+                    }
+                })
+                .sum::<f32>();
+
+            let active = change >= settings.sleep_threshold;
+            if let Some(chunk) = self.chunk_mut(chunk_coord) {
+                chunk.record_activity(active, settings.sleep_after_steps);
+            }
+            if active {
+                for neighbour in coord::neighbourhood(chunk_coord).skip(1) {
+                    if let Some(chunk) = self.chunk_mut(&neighbour) {
+                        if chunk.is_asleep() {
+                            chunk.wake();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn region_volume(&self, region: &[[i32; 2]]) -> f64 {
+        region
+            .iter()
+            .filter_map(|coord| self.chunks().get(coord))
+            .flat_map(|chunk| chunk.tiles().iter())
+            .map(|tile| tile.amount as f64)
+            .sum()
+    }
 
-            //let flow_to_equilibrium = Direction::map_offset(coord)
+    /// Total amount of liquid in all tiles, including the ones too small to be drawn.
+    pub fn total_volume(&self) -> f64 {
+        self.indexed_tiles()
+            .map(|(_, tile)| tile.amount as f64)
+            .sum()
+    }
 
-            let total_velocity = tile.velocity.x + tile.velocity.y + tile.velocity.z + tile.velocity.w;
-            let total_flow_rate = total_velocity.min(tile.amount);
+    /// Moves liquid out of tiles below `threshold` into their fullest open neighbour, so that
+    /// it is not left stranded in tiles that never flow. Returns the moved volume.
+    fn absorb_residue(&mut self, solid: SolidView, threshold: f32) -> f64 {
+        let mut residue = self
+            .indexed_awake_tiles()
+            .filter(|(_, tile)| tile.amount > 0.0 && tile.amount < threshold)
+            .map(|(coord, _)| coord)
+            .collect::<Vec<_>>();
+        residue.sort_unstable();
 
-            let mut total_outflow = 0.0;
-            Direction::for_each_offset(coord, |offset_coord, dir| {
-                let flow_rate = total_flow_rate * (*dir.index_vec(&tile.velocity) / total_velocity);
-                let flow = flow_rate;
-                if flow > 0.0 {
-                    let target = self.get_or_create(&offset_coord);
-                    target.amount += flow;
-                    total_outflow += flow;
-                    //let transfer_velocity = dir.index_vec(&tile.velocity) * (flow / flow_rate);
-                    //*dir.index_vec_mut(&mut target.velocity) += transfer_velocity;
-                    //*dir.index_vec_mut(&mut next.get_or_create(&coord).velocity) -= transfer_velocity;
+        let mut absorbed = 0.0;
+        for coord in residue {
+            let mut target = None;
+            let mut target_amount = threshold;
+            let kind = self.get_or_default(&coord).kind;
+            Direction::for_each_offset(coord, |offset_coord, _| {
+                if solid.blocks_liquid(&offset_coord) {
+                    return;
+                }
+                let neighbour = self.get_or_default(&offset_coord);
+                if neighbour.kind == kind && neighbour.amount >= target_amount {
+                    target = Some(offset_coord);
+                    target_amount = neighbour.amount;
                 }
             });
 
-            let tile = self.get_or_create(&coord);
-            tile.amount -= total_outflow;
+            if let Some(target) = target {
+                let tile = self.get_or_create(&coord);
+                let amount = std::mem::take(&mut tile.amount);
+                self.get_or_create(&target).amount += amount;
+                absorbed += amount as f64;
+            }
+        }
+
+        absorbed
+    }
+
+    /// Swaps every tile with the one above it when the liquid above is denser, so mixed
+    /// columns settle one tile per step. A tile that was just swapped up is not swapped again
+    /// in the same step. Returns the number of swaps.
+    fn settle_layers(&mut self, kinds: &LiquidKinds, threshold: f32) -> usize {
+        let mut filled = self
+            .indexed_awake_tiles()
+            .filter(|(_, tile)| tile.amount >= threshold)
+            .map(|(coord, _)| coord)
+            .collect::<Vec<_>>();
+        filled.sort_unstable();
+
+        let mut swapped = 0;
+        let mut moved_up = HashSet::new();
+        for coord in filled {
+            if moved_up.contains(&coord) {
+                continue;
+            }
+            let above_coord = Direction::Up.offset_from(coord);
+            let below = self.get_or_default(&coord);
+            let above = self.get_or_default(&above_coord);
+            if above.amount >= threshold
+                && above.kind != below.kind
+                && kinds.density(above.kind) > kinds.density(below.kind)
+            {
+                self.set(&coord, above);
+                self.set(&above_coord, below);
+                moved_up.insert(above_coord);
+                swapped += 1;
+            }
         }
+
+        swapped
     }
 
+    fn tile_update(
+        &self,
+        solid: SolidView,
+        coord: [i32; 2],
+        settings: &LiquidSimSettings,
+        params: &KindParams,
+    ) -> TileUpdate {
+        let tile = self.get_or_default(&coord);
+        let params = params.get(tile.kind);
+
+        let gradient = Direction::map_offset(coord, |offset_coord, _| {
+            let amount = self.get_or_default(&offset_coord).amount;
+            tile.amount - amount
+        });
+
+        let gravity = Direction::Down.single_component(1.0) + Direction::Up.single_component(-1.0);
+        let force = (gradient + gravity * params.gravity).max(Vec4::ZERO);
+
+        let mut velocity = tile.velocity * params.damping;
+        //velocity = Direction::normalize(velocity);
+        velocity += force * settings.time_step;
+
+        Direction::for_each_offset(coord, |offset_coord, dir| {
+            *dir.index_vec_mut(&mut velocity) *= solid.permeability(&offset_coord);
+        });
+
+        //let flow_to_equilibrium = Direction::map_offset(coord)
+
+        let total_velocity = velocity.x + velocity.y + velocity.z + velocity.w;
+        let total_flow_rate = total_velocity.min(tile.amount);
+
+        // NaN flows (no velocity at all) are dropped by `max`
+        let desired_outflow = Direction::map(|dir| {
+            (total_flow_rate * (*dir.index_vec(&velocity) / total_velocity)).max(0.0)
+        });
+
+        // Neighbours only take what fits. Down goes first so that liquid settles before it
+        // spreads, and anything over capacity is pushed up last as pressure.
+        let mut remaining = tile.amount;
+        let mut outflow = Vec4::ZERO;
+        for dir in [
+            Direction::Down,
+            Direction::Right,
+            Direction::Left,
+            Direction::Up,
+        ] {
+            let max_inflow = self.get_max_inflow(
+                remaining,
+                tile.kind,
+                solid,
+                dir.offset_from(coord),
+                dir,
+                settings.empty_threshold,
+            );
+            let desired = match dir {
+                Direction::Down | Direction::Up => dir.index_vec(&desired_outflow).max(max_inflow),
+                Direction::Left | Direction::Right => {
+                    dir.index_vec(&desired_outflow)
+                        .max(dir.index_vec(&gradient) / 4.0)
+                        * (1.0 - params.viscosity)
+                }
+            };
+
+            let flow = desired.min(max_inflow).min(remaining).max(0.0);
+            remaining -= flow;
+            *dir.index_vec_mut(&mut outflow) = flow;
+
+            let speed = dir.index_vec_mut(&mut velocity);
+            *speed = speed.min(flow);
+        }
+
+        TileUpdate {
+            kind: tile.kind,
+            velocity,
+            outflow,
+        }
+    }
+
+    /// How much liquid the tile at `point` can take from its neighbour holding `current_amount`
+    /// of `kind`, when the liquid moves in direction `dir`. Permeable solid tiles only take
+    /// their share of it.
     fn get_max_inflow(
         &self,
         current_amount: f32,
-        solid: &GenericTiles<i32, OptTileIndex>,
+        kind: LiquidKind,
+        solid: SolidView,
         point: [i32; 2],
+        dir: Direction,
+        threshold: f32,
     ) -> f32 {
-        if solid.get(&point).and_then(|t| t.get_index()).is_some() {
-            0.0
-        } else {
-            let amount = self.get(&point).map(|t| t.amount).unwrap_or(0.0);
-            let max_inflow = (1.0 - amount).max((current_amount - amount) / 2.0).max(0.0);
-            if max_inflow < 0.01 {
-                0.0
-            } else {
-                max_inflow
+        let permeability = solid.permeability(&point);
+        if permeability <= 0.0 {
+            return 0.0;
+        }
+
+        let target = self.get_or_default(&point);
+        if target.kind != kind && target.amount >= threshold {
+            return 0.0;
+        }
+
+        let amount = target.amount;
+        let total = current_amount + amount;
+        let max_inflow = match dir {
+            Direction::Down => stable_lower_amount(total) - amount,
+            Direction::Up => total - stable_lower_amount(total) - amount,
+            Direction::Left | Direction::Right => {
+                (CAPACITY - amount).max((current_amount - amount) / 2.0)
+            }
+        };
+
+        max_inflow.max(0.0) * permeability
+    }
+}
+
+/// Returns the amount the tile had to be clamped by to stay non-negative.
+fn apply_update(
+    tiles: &mut impl LiquidAccess,
+    coord: [i32; 2],
+    update: TileUpdate,
+    threshold: f32,
+) -> f32 {
+    tiles.tile_mut(coord).velocity = update.velocity;
+
+    let mut total_outflow = 0.0;
+    Direction::for_each_offset(coord, |offset_coord, dir| {
+        let flow = *dir.index_vec(&update.outflow);
+        if flow > 0.0 {
+            let target = tiles.tile_mut(offset_coord);
+            if target.kind != update.kind {
+                // another liquid got here first during this step
+                if target.amount >= threshold {
+                    return;
+                }
+                target.kind = update.kind;
             }
+            target.amount += flow;
+            total_outflow += flow;
+            //let transfer_velocity = dir.index_vec(&tile.velocity) * (flow / flow_rate);
+            //*dir.index_vec_mut(&mut target.velocity) += transfer_velocity;
+            //*dir.index_vec_mut(&mut next.get_or_create(&coord).velocity) -= transfer_velocity;
+        }
+    });
+
+    let tile = tiles.tile_mut(coord);
+    tile.amount -= total_outflow;
+
+    let clamped = (-tile.amount).max(0.0);
+    tile.amount += clamped;
+    clamped
+}
+
+/// Tile storage `apply_update` can write to.
+trait LiquidAccess {
+    fn tile_mut(&mut self, coord: [i32; 2]) -> &mut LiquidTile;
+}
+
+impl LiquidAccess for GenericTiles<i32, LiquidTile> {
+    fn tile_mut(&mut self, coord: [i32; 2]) -> &mut LiquidTile {
+        self.get_or_create(&coord)
+    }
+}
+
+/// A chunk taken out of its `GenericTiles` so it can be updated on another thread. Writes to
+/// tiles of other chunks go to `outside` and are merged back once the pass is done.
+struct DetachedChunk<'a> {
+    coord: [i32; 2],
+    chunk: GenericChunk<LiquidTile>,
+    rest: &'a GenericTiles<i32, LiquidTile>,
+    outside: HashMap<[i32; 2], LiquidTile>,
+}
+
+impl LiquidAccess for DetachedChunk<'_> {
+    fn tile_mut(&mut self, coord: [i32; 2]) -> &mut LiquidTile {
+        let (chunk_coord, inner_coord) = self.rest.split_coord(&coord);
+        if chunk_coord == self.coord {
+            self.chunk.tile_mut(inner_coord)
+        } else {
+            let rest = self.rest;
+            self.outside
+                .entry(coord)
+                .or_insert_with(|| rest.get_or_default(&coord))
+        }
+    }
+}
+
+/// Result of updating a `DetachedChunk`: the chunk itself, the tiles it wrote outside of it
+/// and the clamped volume.
+type ChunkResult = (
+    [i32; 2],
+    GenericChunk<LiquidTile>,
+    HashMap<[i32; 2], LiquidTile>,
+    f64,
+);
+
+impl DetachedChunk<'_> {
+    fn update(
+        mut self,
+        previous: &GenericTiles<i32, LiquidTile>,
+        solid: SolidView,
+        settings: &LiquidSimSettings,
+        params: &KindParams,
+    ) -> ChunkResult {
+        let mut active_tiles = self
+            .chunk
+            .indexed_tiles()
+            .filter(|(_, tile)| tile.amount >= settings.empty_threshold)
+            .map(|(inner_coord, _)| self.rest.combine_coord(&self.coord, &inner_coord))
+            .collect::<Vec<_>>();
+        active_tiles.sort_unstable();
+
+        let mut clamped = 0.0;
+        for coord in active_tiles {
+            let update = previous.tile_update(solid, coord, settings, params);
+            clamped += apply_update(&mut self, coord, update, settings.empty_threshold) as f64;
         }
+
+        (self.coord, self.chunk, self.outside, clamped)
+    }
+}
+
+/// Chunks are updated in four passes by the parity of their coordinates. Chunks of one pass
+/// are at least a chunk apart, and updates write at most one tile past their own chunk, so
+/// the chunks of a pass never touch the same tile and can be updated in any order.
+const CHUNK_PASSES: [[i32; 2]; 4] = [[0, 0], [1, 0], [0, 1], [1, 1]];
+
+/// How much of `total` the lower of two stacked tiles holds at rest.
+///
+/// The lower tile fills up first and then holds `COMPRESSION` more than the upper one for
+/// every full tile of liquid above it, which is what lets pressure push liquid back up.
+fn stable_lower_amount(total: f32) -> f32 {
+    if total <= CAPACITY {
+        total
+    } else if total < 2.0 * CAPACITY + COMPRESSION {
+        (CAPACITY * CAPACITY + total * COMPRESSION) / (CAPACITY + COMPRESSION)
+    } else {
+        (total + COMPRESSION) / 2.0
     }
 }
 
@@ -196,3 +700,288 @@ impl std::fmt::Display for LiquidTile {
         write!(f, "{}", amount)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tiles::tile_type::{TileDef, TileTypes};
+    use crate::tiles::OptTileIndex;
+
+    fn scene(
+        points: &[[i32; 2]],
+    ) -> (
+        GenericTiles<i32, LiquidTile>,
+        GenericTiles<i32, OptTileIndex>,
+    ) {
+        let mut liquid = GenericTiles::new(4);
+        let mut solid = GenericTiles::new(4);
+        for x in -6..6 {
+            solid.set(&[x, -3], OptTileIndex::from_index(0));
+        }
+        for point in points {
+            liquid.set(point, LiquidTile::new(1.0 + point[0] as f32 * 0.25));
+        }
+        (liquid, solid)
+    }
+
+    fn sorted_tiles(liquid: &GenericTiles<i32, LiquidTile>) -> Vec<([i32; 2], f32, [f32; 4])> {
+        let mut tiles = liquid
+            .indexed_tiles()
+            .map(|(coord, tile)| (coord, tile.amount, tile.velocity.to_array()))
+            .collect::<Vec<_>>();
+        tiles.sort_by_key(|(coord, _, _)| *coord);
+        tiles
+    }
+
+    #[test]
+    fn u_tube_levels_out() {
+        let kinds = LiquidKinds::default();
+        let types = TileTypes::default();
+        let settings = LiquidSimSettings::default();
+        let mut liquid = GenericTiles::new(4);
+        let mut solid = GenericTiles::new(4);
+        for x in 0..=4 {
+            solid.set(&[x, 0], OptTileIndex::from_index(0));
+        }
+        for y in 1..=10 {
+            solid.set(&[0, y], OptTileIndex::from_index(0));
+            solid.set(&[4, y], OptTileIndex::from_index(0));
+        }
+        for y in 2..=10 {
+            solid.set(&[2, y], OptTileIndex::from_index(0));
+        }
+        for y in 1..=7 {
+            liquid.set(&[1, y], LiquidTile::new(1.0));
+        }
+
+        let column = |liquid: &GenericTiles<i32, LiquidTile>, x| {
+            (1..=10)
+                .map(|y| liquid.get_or_default(&[x, y]).amount)
+                .sum::<f32>()
+        };
+
+        for _ in 0..2000 {
+            liquid.step_with_mode(
+                SolidView::new(&solid, &types),
+                &kinds,
+                &settings,
+                LiquidStepMode::Deterministic,
+            );
+        }
+
+        let (left, right) = (column(&liquid, 1), column(&liquid, 3));
+        assert!((left - right).abs() < 0.05, "{} != {}", left, right);
+    }
+
+    #[test]
+    fn lighter_liquid_floats() {
+        let kinds = LiquidKinds::default();
+        let types = TileTypes::default();
+        let settings = LiquidSimSettings::default();
+        let mut liquid = GenericTiles::new(4);
+        let mut solid = GenericTiles::new(4);
+        solid.set(&[0, 0], OptTileIndex::from_index(0));
+        for y in 1..=6 {
+            solid.set(&[-1, y], OptTileIndex::from_index(0));
+            solid.set(&[1, y], OptTileIndex::from_index(0));
+        }
+        for y in 1..=2 {
+            liquid.set(&[0, y], LiquidTile::with_kind(1.0, LiquidKind::OIL));
+        }
+        for y in 3..=4 {
+            liquid.set(&[0, y], LiquidTile::with_kind(1.0, LiquidKind::WATER));
+        }
+
+        for _ in 0..100 {
+            liquid.step_with_mode(
+                SolidView::new(&solid, &types),
+                &kinds,
+                &settings,
+                LiquidStepMode::Deterministic,
+            );
+        }
+
+        let kind_at = |y| liquid.get_or_default(&[0, y]).kind;
+        assert_eq!([kind_at(1), kind_at(2)], [LiquidKind::WATER; 2]);
+        assert_eq!([kind_at(3), kind_at(4)], [LiquidKind::OIL; 2]);
+    }
+
+    #[test]
+    fn layers_settle_one_tile_per_step() {
+        let kinds = LiquidKinds::default();
+        let mut liquid = GenericTiles::new(8);
+        liquid.set(&[0, 0], LiquidTile::with_kind(1.0, LiquidKind::OIL));
+        for y in 1..=3 {
+            liquid.set(&[0, y], LiquidTile::with_kind(1.0, LiquidKind::WATER));
+        }
+
+        assert_eq!(liquid.settle_layers(&kinds, EMPTY_THRESHOLD), 1);
+        assert_eq!(liquid.get_or_default(&[0, 1]).kind, LiquidKind::OIL);
+        assert_eq!(liquid.get_or_default(&[0, 3]).kind, LiquidKind::WATER);
+    }
+
+    #[test]
+    fn conserves_volume() {
+        let kinds = LiquidKinds::default();
+        let types = TileTypes::default();
+        let settings = LiquidSimSettings::default();
+        let (mut liquid, solid) = scene(&[[-4, 2], [0, 0], [3, 5], [-1, -2], [5, 1]]);
+        liquid.set(&[1, 1], LiquidTile::new(0.005));
+
+        let initial = liquid.total_volume();
+        for mode in [LiquidStepMode::InPlace, LiquidStepMode::Deterministic] {
+            for _ in 0..100 {
+                let report =
+                    liquid.step_with_mode(SolidView::new(&solid, &types), &kinds, &settings, mode);
+                assert!(report.delta().abs() < 1e-4);
+            }
+        }
+        assert!((liquid.total_volume() - initial).abs() < 1e-3);
+    }
+
+    #[test]
+    fn still_liquid_sleeps() {
+        let kinds = LiquidKinds::default();
+        let types = TileTypes::default();
+        let settings = LiquidSimSettings::default();
+        let (mut liquid, mut solid) = scene(&[[0, -2], [1, -2], [2, -2]]);
+        for y in -2..=0 {
+            solid.set(&[-1, y], OptTileIndex::from_index(0));
+            solid.set(&[3, y], OptTileIndex::from_index(0));
+        }
+
+        let mut report = LiquidStepReport::default();
+        for _ in 0..500 {
+            report = liquid.step(SolidView::new(&solid, &types), &kinds, &settings);
+        }
+        assert_eq!(report.awake_chunks, 0);
+        let settled = sorted_tiles(&liquid);
+
+        liquid.step(SolidView::new(&solid, &types), &kinds, &settings);
+        assert_eq!(sorted_tiles(&liquid), settled);
+
+        let pool = |liquid: &GenericTiles<i32, LiquidTile>| {
+            (0..=2)
+                .map(|x| liquid.get_or_default(&[x, -2]).amount)
+                .sum::<f32>()
+        };
+        let full = pool(&liquid);
+        solid.set(&[3, -2], OptTileIndex::empty());
+        liquid.wake_around(&[3, -2]);
+        for _ in 0..50 {
+            liquid.step(SolidView::new(&solid, &types), &kinds, &settings);
+        }
+        assert!(pool(&liquid) < full - 0.5, "{} -> {}", full, pool(&liquid));
+    }
+
+    #[test]
+    fn deterministic_step() {
+        let kinds = LiquidKinds::default();
+        let types = TileTypes::default();
+        let settings = LiquidSimSettings::default();
+        let points = [[-4, 2], [0, 0], [3, 5], [-1, -2], [5, 1]];
+        let mut reversed = points;
+        reversed.reverse();
+
+        let (mut a, solid) = scene(&points);
+        let (mut b, _) = scene(&reversed);
+        for _ in 0..50 {
+            a.step_with_mode(
+                SolidView::new(&solid, &types),
+                &kinds,
+                &settings,
+                LiquidStepMode::Deterministic,
+            );
+            b.step_with_mode(
+                SolidView::new(&solid, &types),
+                &kinds,
+                &settings,
+                LiquidStepMode::Deterministic,
+            );
+        }
+
+        let a = sorted_tiles(&a);
+        let b = sorted_tiles(&b);
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(&b) {
+            assert_eq!(a.0, b.0);
+            assert_eq!(a.1.to_bits(), b.1.to_bits());
+            for (a, b) in a.2.iter().zip(&b.2) {
+                assert_eq!(a.to_bits(), b.to_bits());
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_step_matches_serial() {
+        let kinds = LiquidKinds::default();
+        let types = TileTypes::default();
+        let settings = LiquidSimSettings::default();
+        let pool = TaskPool::new();
+        let points = (-12..12)
+            .flat_map(|x| [[x, 3], [x, 7 + x.rem_euclid(3)]])
+            .collect::<Vec<_>>();
+
+        let (mut serial, mut solid) = scene(&points);
+        for x in -12..12 {
+            solid.set(&[x, x.rem_euclid(5)], OptTileIndex::from_index(0));
+        }
+        for point in &points[..8] {
+            serial.set(point, LiquidTile::with_kind(1.0, LiquidKind::OIL));
+        }
+        let mut parallel = serial.clone();
+
+        for _ in 0..50 {
+            let mode = LiquidStepMode::Deterministic;
+            let a = serial.step_on_pool(
+                SolidView::new(&solid, &types),
+                &kinds,
+                &settings,
+                mode,
+                None,
+            );
+            let b = parallel.step_on_pool(
+                SolidView::new(&solid, &types),
+                &kinds,
+                &settings,
+                mode,
+                Some(&pool),
+            );
+            assert_eq!(a, b);
+        }
+        assert_eq!(sorted_tiles(&serial), sorted_tiles(&parallel));
+    }
+
+    #[test]
+    fn liquid_seeps_through_permeable_tiles() {
+        let kinds = LiquidKinds::default();
+        let settings = LiquidSimSettings::default();
+        let mut types = TileTypes::default();
+        let grate = types.register(TileDef {
+            permeability: 0.5,
+            ..TileDef::wall("grate", 0)
+        });
+        let stone = OptTileIndex::from_index(0);
+
+        let below = |floor| {
+            let mut liquid = GenericTiles::new(4);
+            let mut solid = GenericTiles::new(4);
+            solid.set(&[0, -3], stone);
+            for y in -3..=3 {
+                solid.set(&[-1, y], stone);
+                solid.set(&[1, y], stone);
+            }
+            solid.set(&[0, 0], floor);
+            liquid.set(&[0, 1], LiquidTile::new(1.0));
+            for _ in 0..200 {
+                liquid.step(SolidView::new(&solid, &types), &kinds, &settings);
+            }
+            (-2..=0)
+                .map(|y| liquid.get_or_default(&[0, y]).amount)
+                .sum::<f32>()
+        };
+
+        assert_eq!(below(stone), 0.0);
+        assert!(below(grate) > 0.9, "{}", below(grate));
+    }
+}
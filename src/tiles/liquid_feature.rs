@@ -0,0 +1,224 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use bevy::tasks::TaskPool;
+
+use super::generic_tiles::*;
+use super::liquid::{LiquidStepMode, LiquidStepReport, LiquidTile, CAPACITY};
+use super::liquid_kind::{LiquidKind, LiquidKinds};
+use super::liquid_settings::LiquidSimSettings;
+use super::tile_type::SolidView;
+
+/// Something placed on a tile that changes the amount of liquid in it every step.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LiquidFeature {
+    /// Adds `rate` of `liquid` every step until the tile is full. Does nothing while the tile
+    /// holds another liquid.
+    Source { liquid: LiquidKind, rate: f32 },
+    /// Removes up to `rate` of any liquid every step.
+    Drain { rate: f32 },
+}
+
+/// Sources and drains of a `LiquidTiles` layer, by tile coordinate.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(
+    from = "Vec<([i32; 2], LiquidFeature)>",
+    into = "Vec<([i32; 2], LiquidFeature)>"
+)]
+pub struct LiquidFeatures {
+    features: BTreeMap<[i32; 2], LiquidFeature>,
+}
+
+impl LiquidFeatures {
+    pub fn insert(&mut self, point: [i32; 2], feature: LiquidFeature) -> Option<LiquidFeature> {
+        self.features.insert(point, feature)
+    }
+
+    pub fn remove(&mut self, point: &[i32; 2]) -> Option<LiquidFeature> {
+        self.features.remove(point)
+    }
+
+    pub fn get(&self, point: &[i32; 2]) -> Option<&LiquidFeature> {
+        self.features.get(point)
+    }
+
+    /// Iterates features in sorted coordinate order.
+    pub fn iter(&self) -> impl Iterator<Item = (&[i32; 2], &LiquidFeature)> {
+        self.features.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.features.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.features.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.features.clear();
+    }
+}
+
+impl From<Vec<([i32; 2], LiquidFeature)>> for LiquidFeatures {
+    fn from(features: Vec<([i32; 2], LiquidFeature)>) -> Self {
+        LiquidFeatures {
+            features: features.into_iter().collect(),
+        }
+    }
+}
+
+impl From<LiquidFeatures> for Vec<([i32; 2], LiquidFeature)> {
+    fn from(features: LiquidFeatures) -> Self {
+        features.features.into_iter().collect()
+    }
+}
+
+/// Liquid added and removed by `GenericTiles::apply_features`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct FeatureFlow {
+    pub emitted: f64,
+    pub drained: f64,
+}
+
+impl GenericTiles<i32, LiquidTile> {
+    /// Runs every source and drain once. Features covered by a tile that blocks liquid do
+    /// nothing. Chunks around features that changed their tile are woken up.
+    pub fn apply_features(
+        &mut self,
+        solid: SolidView,
+        features: &LiquidFeatures,
+        threshold: f32,
+    ) -> FeatureFlow {
+        let mut flow = FeatureFlow::default();
+        for (point, feature) in features.iter() {
+            if solid.blocks_liquid(point) {
+                continue;
+            }
+
+            let tile = self.get_or_default(point);
+            let changed = match *feature {
+                LiquidFeature::Source { liquid, rate } => {
+                    if tile.kind != liquid && !tile.is_empty(threshold) {
+                        continue;
+                    }
+                    let added = rate.min(CAPACITY - tile.amount).max(0.0);
+                    if added > 0.0 {
+                        let tile = self.get_or_create(point);
+                        tile.kind = liquid;
+                        tile.amount += added;
+                    }
+                    flow.emitted += added as f64;
+                    added
+                }
+                LiquidFeature::Drain { rate } => {
+                    let removed = rate.min(tile.amount).max(0.0);
+                    if removed > 0.0 {
+                        self.get_or_create(point).amount -= removed;
+                    }
+                    flow.drained += removed as f64;
+                    removed
+                }
+            };
+
+            if changed > 0.0 {
+                self.wake_around(point);
+            }
+        }
+        flow
+    }
+
+    /// Runs the features and then a step, with the liquid the features added and removed in
+    /// the report.
+    pub fn step_with_features(
+        &mut self,
+        solid: SolidView,
+        features: &LiquidFeatures,
+        kinds: &LiquidKinds,
+        settings: &LiquidSimSettings,
+        mode: LiquidStepMode,
+        pool: Option<&TaskPool>,
+    ) -> LiquidStepReport {
+        let flow = self.apply_features(solid, features, settings.empty_threshold);
+        LiquidStepReport {
+            emitted: flow.emitted,
+            drained: flow.drained,
+            ..self.step_on_pool(solid, kinds, settings, mode, pool)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tiles::liquid::EMPTY_THRESHOLD;
+    use crate::tiles::tile_type::TileTypes;
+    use crate::tiles::OptTileIndex;
+
+    #[test]
+    fn source_fills_and_drain_empties() {
+        let solid = GenericTiles::new(4);
+        let types = TileTypes::default();
+        let mut liquid = GenericTiles::new(4);
+        let mut features = LiquidFeatures::default();
+        features.insert(
+            [0, 0],
+            LiquidFeature::Source {
+                liquid: LiquidKind::OIL,
+                rate: 0.3,
+            },
+        );
+        features.insert([5, 0], LiquidFeature::Drain { rate: 0.25 });
+        liquid.set(&[5, 0], LiquidTile::new(0.4));
+
+        for _ in 0..5 {
+            liquid.apply_features(SolidView::new(&solid, &types), &features, EMPTY_THRESHOLD);
+        }
+
+        let source = liquid.get_or_default(&[0, 0]);
+        assert_eq!(source.amount, CAPACITY);
+        assert_eq!(source.kind, LiquidKind::OIL);
+        assert_eq!(liquid.get_or_default(&[5, 0]).amount, 0.0);
+    }
+
+    #[test]
+    fn report_accounts_for_features() {
+        let kinds = LiquidKinds::default();
+        let settings = LiquidSimSettings::default();
+        let types = TileTypes::default();
+        let mut solid = GenericTiles::new(4);
+        for x in -1..=6 {
+            solid.set(&[x, -1], OptTileIndex::from_index(0));
+        }
+        solid.set(&[-1, 0], OptTileIndex::from_index(0));
+        solid.set(&[6, 0], OptTileIndex::from_index(0));
+        let mut liquid = GenericTiles::new(4);
+        let mut features = LiquidFeatures::default();
+        features.insert(
+            [0, 0],
+            LiquidFeature::Source {
+                liquid: LiquidKind::WATER,
+                rate: 0.1,
+            },
+        );
+        features.insert([5, 0], LiquidFeature::Drain { rate: 0.02 });
+
+        let (mut emitted, mut drained) = (0.0, 0.0);
+        for _ in 0..200 {
+            let report = liquid.step_with_features(
+                SolidView::new(&solid, &types),
+                &features,
+                &kinds,
+                &settings,
+                LiquidStepMode::Deterministic,
+                None,
+            );
+            emitted += report.emitted;
+            drained += report.drained;
+        }
+
+        assert!(emitted > 0.0 && drained > 0.0, "{} {}", emitted, drained);
+        let volume = liquid.total_volume();
+        assert!((volume - (emitted - drained)).abs() < 1e-3, "{}", volume);
+    }
+}
@@ -0,0 +1,97 @@
+use bevy::prelude::Color;
+use serde::{Deserialize, Serialize};
+
+/// Index of a liquid in `LiquidKinds`.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct LiquidKind(pub u16);
+
+impl LiquidKind {
+    pub const WATER: LiquidKind = LiquidKind(0);
+    pub const OIL: LiquidKind = LiquidKind(1);
+    pub const LAVA: LiquidKind = LiquidKind(2);
+    pub const ACID: LiquidKind = LiquidKind(3);
+}
+
+#[derive(Debug, Clone)]
+pub struct LiquidKindDef {
+    pub name: String,
+    /// Denser liquids sink below lighter ones.
+    pub density: f32,
+    pub color: Color,
+}
+
+/// Every liquid a `LiquidTiles` layer can hold. The first entries are always the built-in
+/// kinds, in the order of the `LiquidKind` constants.
+#[derive(Debug, Clone)]
+pub struct LiquidKinds {
+    kinds: Vec<LiquidKindDef>,
+}
+
+impl Default for LiquidKinds {
+    fn default() -> Self {
+        LiquidKinds {
+            kinds: vec![
+                LiquidKindDef {
+                    name: "water".to_string(),
+                    density: 1.0,
+                    color: Color::rgb(0.3, 0.5, 1.0),
+                },
+                LiquidKindDef {
+                    name: "oil".to_string(),
+                    density: 0.8,
+                    color: Color::rgb(0.35, 0.25, 0.1),
+                },
+                LiquidKindDef {
+                    name: "lava".to_string(),
+                    density: 3.0,
+                    color: Color::rgb(1.0, 0.35, 0.0),
+                },
+                LiquidKindDef {
+                    name: "acid".to_string(),
+                    density: 1.1,
+                    color: Color::rgb(0.4, 1.0, 0.2),
+                },
+            ],
+        }
+    }
+}
+
+impl LiquidKinds {
+    pub fn register(&mut self, def: LiquidKindDef) -> LiquidKind {
+        self.kinds.push(def);
+        LiquidKind((self.kinds.len() - 1) as u16)
+    }
+
+    /// Unknown kinds, e.g. from a map saved with more kinds registered, behave like water.
+    pub fn get(&self, kind: LiquidKind) -> &LiquidKindDef {
+        self.kinds
+            .get(kind.0 as usize)
+            .unwrap_or(&self.kinds[LiquidKind::WATER.0 as usize])
+    }
+
+    pub fn density(&self, kind: LiquidKind) -> f32 {
+        self.get(kind).density
+    }
+
+    pub fn find(&self, name: &str) -> Option<LiquidKind> {
+        self.kinds
+            .iter()
+            .position(|def| def.name == name)
+            .map(|index| LiquidKind(index as u16))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (LiquidKind, &LiquidKindDef)> {
+        self.kinds
+            .iter()
+            .enumerate()
+            .map(|(index, def)| (LiquidKind(index as u16), def))
+    }
+
+    /// The kind after `kind`, wrapping around to water.
+    pub fn next(&self, kind: LiquidKind) -> LiquidKind {
+        LiquidKind((kind.0 + 1) % self.kinds.len() as u16)
+    }
+}
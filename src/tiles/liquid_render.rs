@@ -0,0 +1,101 @@
+use bevy::prelude::*;
+
+use super::generic_tiles::*;
+use super::liquid::{LiquidTile, CAPACITY};
+use super::sync::TileObject;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LiquidRenderMode {
+    /// Every tile holding liquid is drawn as a full tile.
+    Tiles,
+    /// Tiles are filled up to their liquid level, and the surface is smoothed between
+    /// neighbouring columns.
+    Level,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiquidRenderSettings {
+    pub mode: LiquidRenderMode,
+    /// Draws the amount of every tile on top of it, rounded to one decimal, and logs the
+    /// reactions of the map.
+    pub debug_labels: bool,
+}
+
+impl Default for LiquidRenderSettings {
+    fn default() -> Self {
+        LiquidRenderSettings {
+            mode: LiquidRenderMode::Level,
+            debug_labels: false,
+        }
+    }
+}
+
+/// Number of vertical strips a surface tile is split into.
+const SURFACE_STRIPS: usize = 4;
+
+/// Objects for a tile in `LiquidRenderMode::Level`. Tiles below the surface are filled up to
+/// their level, surface tiles are split into strips that lean towards the neighbouring levels.
+/// Tiles below `threshold` count as empty.
+pub fn level_objects(
+    tiles: &GenericTiles<i32, LiquidTile>,
+    [x, y]: [i32; 2],
+    tile: &LiquidTile,
+    threshold: f32,
+    color: Color,
+    image: &Handle<Image>,
+) -> Vec<TileObject> {
+    let level = fill_level(tile);
+    let column = |offset_x: f32, width: f32, level: f32| TileObject::Sprite {
+        color,
+        image: image.clone(),
+        offset: Vec2::new(offset_x, (level - 1.0) / 2.0),
+        size: Vec2::new(width, level),
+    };
+
+    if !is_surface(tiles, [x, y], threshold) {
+        return vec![column(0.0, 1.0, level)];
+    }
+
+    let left = neighbour_level(tiles, tile, [x - 1, y], threshold);
+    let right = neighbour_level(tiles, tile, [x + 1, y], threshold);
+    let strip_width = 1.0 / SURFACE_STRIPS as f32;
+    (0..SURFACE_STRIPS)
+        .map(|strip| {
+            // position of the strip centre from -0.5 to 0.5 across the tile
+            let t = (strip as f32 + 0.5) * strip_width - 0.5;
+            let edge = if t < 0.0 { left } else { right };
+            column(t, strip_width, level + (edge - level) * t.abs() * 2.0)
+        })
+        .collect()
+}
+
+fn fill_level(tile: &LiquidTile) -> f32 {
+    (tile.amount / CAPACITY).clamp(0.0, 1.0)
+}
+
+fn is_surface(tiles: &GenericTiles<i32, LiquidTile>, [x, y]: [i32; 2], threshold: f32) -> bool {
+    tiles
+        .get(&[x, y + 1])
+        .map_or(true, |above| above.is_empty(threshold))
+}
+
+/// Level the surface of `tile` leans towards at its edge next to `point`. Walls, air and other
+/// liquids keep the surface flat, a full column next to it pulls it up.
+fn neighbour_level(
+    tiles: &GenericTiles<i32, LiquidTile>,
+    tile: &LiquidTile,
+    point: [i32; 2],
+    threshold: f32,
+) -> f32 {
+    let own = fill_level(tile);
+    match tiles.get(&point) {
+        Some(neighbour) if !neighbour.is_empty(threshold) && neighbour.kind == tile.kind => {
+            if is_surface(tiles, point, threshold) {
+                (own + fill_level(neighbour)) / 2.0
+            } else {
+                (own + 1.0) / 2.0
+            }
+        }
+        _ => own,
+    }
+}
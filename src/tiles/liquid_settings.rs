@@ -0,0 +1,209 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::liquid::EMPTY_THRESHOLD;
+use super::liquid_kind::{LiquidKind, LiquidKinds};
+
+/// How a single liquid kind moves.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LiquidParams {
+    /// Fraction of its velocity a tile keeps from one step to the next.
+    pub damping: f32,
+    /// Downward force added to the velocity every step.
+    pub gravity: f32,
+    /// Fraction of sideways flow held back every step. `0.0` flows freely, honey is close to `1.0`.
+    pub viscosity: f32,
+}
+
+impl Default for LiquidParams {
+    fn default() -> Self {
+        LiquidParams {
+            damping: 0.9,
+            gravity: 0.1,
+            viscosity: 0.0,
+        }
+    }
+}
+
+/// Simulation parameters read by `liquid_sim` before every step, so changes made at runtime
+/// apply from the next step on. Fields missing from a settings file keep their defaults.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LiquidSimSettings {
+    /// Simulated time covered by a single step.
+    pub time_step: f32,
+    /// Number of steps per second of real time, before `speed` is applied.
+    pub tick_rate: f32,
+    /// Multiplier on `tick_rate`. `0.0` pauses the simulation.
+    pub speed: f32,
+    /// Most steps run in a single frame. Time the simulation falls behind by beyond that is
+    /// dropped, so a slow frame does not make the next ones even slower.
+    pub max_catch_up_steps: u32,
+    /// Chunks whose tiles change by less than this in total during a step count as quiet.
+    pub sleep_threshold: f32,
+    /// Number of quiet steps in a row after which a chunk is no longer simulated.
+    pub sleep_after_steps: u32,
+    /// Tiles holding less liquid than this do not flow on their own and can be taken over by
+    /// another kind.
+    pub empty_threshold: f32,
+    /// Parameters of every kind without an entry in `overrides`.
+    pub default_params: LiquidParams,
+    /// Parameters by kind name, as kinds are registered per map.
+    pub overrides: HashMap<String, LiquidParams>,
+}
+
+impl Default for LiquidSimSettings {
+    fn default() -> Self {
+        LiquidSimSettings {
+            time_step: 0.1,
+            tick_rate: 60.0,
+            speed: 1.0,
+            max_catch_up_steps: 4,
+            sleep_threshold: 1e-4,
+            sleep_after_steps: 60,
+            empty_threshold: EMPTY_THRESHOLD,
+            default_params: LiquidParams::default(),
+            overrides: HashMap::from([(
+                "lava".to_string(),
+                LiquidParams {
+                    damping: 0.8,
+                    gravity: 0.1,
+                    viscosity: 0.6,
+                },
+            )]),
+        }
+    }
+}
+
+impl LiquidSimSettings {
+    pub fn from_ron(text: &str) -> Result<Self, ron::Error> {
+        ron::de::from_str(text)
+    }
+
+    /// Parameters of every kind in `kinds`.
+    pub fn params(&self, kinds: &LiquidKinds) -> KindParams {
+        KindParams {
+            params: kinds
+                .iter()
+                .map(|(_, def)| {
+                    self.overrides
+                        .get(&def.name)
+                        .copied()
+                        .unwrap_or(self.default_params)
+                })
+                .collect(),
+            default: self.default_params,
+        }
+    }
+
+    pub fn set_params(&mut self, kind_name: &str, params: LiquidParams) {
+        self.overrides.insert(kind_name.to_string(), params);
+    }
+}
+
+/// `LiquidSimSettings::overrides` looked up in the `LiquidKinds` of one map.
+#[derive(Debug, Clone)]
+pub struct KindParams {
+    params: Vec<LiquidParams>,
+    default: LiquidParams,
+}
+
+impl KindParams {
+    pub fn get(&self, kind: LiquidKind) -> LiquidParams {
+        self.params
+            .get(kind.0 as usize)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// Turns frame times into a whole number of fixed-rate simulation steps.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LiquidSimClock {
+    accumulator: f32,
+}
+
+impl LiquidSimClock {
+    /// Adds `delta` seconds of real time and returns how many steps to run for it.
+    pub fn advance(&mut self, delta: f32, settings: &LiquidSimSettings) -> u32 {
+        let tick_rate = settings.tick_rate * settings.speed;
+        if tick_rate <= 0.0 {
+            return 0;
+        }
+
+        self.accumulator += delta * tick_rate;
+        let steps = self.accumulator.floor();
+        if steps > settings.max_catch_up_steps as f32 {
+            self.accumulator = 0.0;
+            settings.max_catch_up_steps
+        } else {
+            self.accumulator -= steps;
+            steps as u32
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tiles::liquid_kind::LiquidKindDef;
+
+    #[test]
+    fn clock_is_frame_rate_independent() {
+        let settings = LiquidSimSettings::default();
+
+        let mut slow = LiquidSimClock::default();
+        let mut fast = LiquidSimClock::default();
+        let slow_steps = (0..60)
+            .map(|_| slow.advance(1.0 / 30.0, &settings))
+            .sum::<u32>();
+        let fast_steps = (0..120)
+            .map(|_| fast.advance(1.0 / 60.0, &settings))
+            .sum::<u32>();
+        assert!((slow_steps as i32 - 120).abs() <= 1, "{}", slow_steps);
+        assert!((fast_steps as i32 - 120).abs() <= 1, "{}", fast_steps);
+
+        let mut stalled = LiquidSimClock::default();
+        assert_eq!(
+            stalled.advance(10.0, &settings),
+            settings.max_catch_up_steps
+        );
+        assert_eq!(stalled.advance(0.0, &settings), 0);
+    }
+
+    #[test]
+    fn overrides_follow_kind_names() {
+        let text = include_str!("../../assets/liquid_settings.ron");
+        let mut settings = LiquidSimSettings::from_ron(text).unwrap();
+        let honey = LiquidParams {
+            viscosity: 0.9,
+            ..settings.default_params
+        };
+        settings.set_params("honey", honey);
+
+        // two maps that registered honey under different indices
+        let def = |name: &str| LiquidKindDef {
+            name: name.to_string(),
+            ..LiquidKinds::default().get(LiquidKind::WATER).clone()
+        };
+        let mut kinds = LiquidKinds::default();
+        let first = kinds.register(def("honey"));
+        let mut other_kinds = LiquidKinds::default();
+        other_kinds.register(def("mud"));
+        let second = other_kinds.register(def("honey"));
+        assert_ne!(first, second);
+        assert_eq!(settings.params(&kinds).get(first), honey);
+        assert_eq!(settings.params(&other_kinds).get(second), honey);
+
+        let params = settings.params(&kinds);
+        assert_eq!(params.get(LiquidKind::LAVA), settings.overrides["lava"]);
+        assert_eq!(params.get(LiquidKind::WATER), settings.default_params);
+        assert_eq!(params.get(LiquidKind(100)), settings.default_params);
+
+        // missing fields keep their defaults
+        let partial = LiquidSimSettings::from_ron("(speed: 2.0)").unwrap();
+        assert_eq!(partial.speed, 2.0);
+        assert_eq!(partial.tick_rate, LiquidSimSettings::default().tick_rate);
+    }
+}
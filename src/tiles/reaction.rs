@@ -0,0 +1,404 @@
+use serde::{Deserialize, Serialize};
+
+use super::generic_tiles::*;
+use super::liquid::{Direction, LiquidTile};
+use super::liquid_kind::{LiquidKind, LiquidKinds};
+use super::tile_type::{SolidView, TileTypes};
+use super::OptTileIndex;
+
+/// What a liquid has to touch for a reaction to happen.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReactionPartner {
+    /// A liquid of the kind with the given name.
+    Liquid(String),
+    /// A solid tile of the given type, or any tile that collides for `None`.
+    Solid(Option<u32>),
+}
+
+/// Liquid kinds are registered per map, so reactions name them, and every map looks the names
+/// up in its own `LiquidKinds`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiquidReaction {
+    pub name: String,
+    /// Name of the reacting liquid kind.
+    pub liquid: String,
+    pub partner: ReactionPartner,
+    /// Volume of `liquid` used up every time the reaction happens. With a solid partner it is
+    /// scaled by the partner's `TileDef::hardness`, and tiles holding less do not react.
+    pub consumes: f32,
+    /// Volume of the partner liquid used up. Ignored for solid partners.
+    #[serde(default)]
+    pub consumes_partner: f32,
+    /// Solid tile that replaces the reacting liquid. All of its liquid is used up.
+    #[serde(default)]
+    pub product: Option<OptTileIndex>,
+    /// Solid tile written where the partner was. An empty tile erodes a solid partner.
+    #[serde(default)]
+    pub partner_product: Option<OptTileIndex>,
+}
+
+/// Table of reactions checked after every liquid step. The first matching reaction wins,
+/// and each liquid tile reacts at most once per step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiquidReactions {
+    pub reactions: Vec<LiquidReaction>,
+}
+
+impl Default for LiquidReactions {
+    fn default() -> Self {
+        LiquidReactions {
+            reactions: vec![
+                LiquidReaction {
+                    name: "obsidian".to_string(),
+                    liquid: "lava".to_string(),
+                    partner: ReactionPartner::Liquid("water".to_string()),
+                    consumes: f32::INFINITY,
+                    consumes_partner: 0.5,
+                    product: Some(OptTileIndex::from_index(0)),
+                    partner_product: None,
+                },
+                LiquidReaction {
+                    name: "erosion".to_string(),
+                    liquid: "acid".to_string(),
+                    partner: ReactionPartner::Solid(None),
+                    consumes: 0.25,
+                    consumes_partner: 0.0,
+                    product: None,
+                    partner_product: Some(OptTileIndex::empty()),
+                },
+            ],
+        }
+    }
+}
+
+impl LiquidReactions {
+    pub fn from_ron(text: &str) -> Result<Self, ron::Error> {
+        ron::de::from_str(text)
+    }
+
+    /// Looks the liquid names of every reaction up in `kinds`. Reactions naming a kind that
+    /// is not in there are `None`, so they never happen.
+    fn resolve(&self, kinds: &LiquidKinds) -> Vec<Option<ResolvedReaction>> {
+        self.reactions
+            .iter()
+            .map(|reaction| {
+                let partner = match &reaction.partner {
+                    ReactionPartner::Liquid(name) => Partner::Liquid(kinds.find(name)?),
+                    ReactionPartner::Solid(index) => Partner::Solid(*index),
+                };
+                Some(ResolvedReaction {
+                    liquid: kinds.find(&reaction.liquid)?,
+                    partner,
+                    consumes: reaction.consumes,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A `LiquidReaction` with its liquid names looked up in the `LiquidKinds` of one map.
+#[derive(Debug, Clone, Copy)]
+struct ResolvedReaction {
+    liquid: LiquidKind,
+    partner: Partner,
+    consumes: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Partner {
+    Liquid(LiquidKind),
+    Solid(Option<u32>),
+}
+
+/// The first of `resolved` that `tile` at `coord` takes part in, and where its partner is.
+fn find_reaction(
+    resolved: &[Option<ResolvedReaction>],
+    liquid: &GenericTiles<i32, LiquidTile>,
+    solid: SolidView,
+    coord: [i32; 2],
+    tile: &LiquidTile,
+    threshold: f32,
+) -> Option<(usize, [i32; 2])> {
+    resolved
+        .iter()
+        .enumerate()
+        .filter_map(|(index, reaction)| Some((index, (*reaction)?)))
+        .filter(|(_, reaction)| reaction.liquid == tile.kind)
+        .find_map(|(index, reaction)| {
+            Direction::all()
+                .into_iter()
+                .map(|dir| dir.offset_from(coord))
+                .find(|point| reaction.matches(liquid, solid, tile, point, threshold))
+                .map(|point| (index, point))
+        })
+}
+
+impl ResolvedReaction {
+    fn matches(
+        &self,
+        liquid: &GenericTiles<i32, LiquidTile>,
+        solid: SolidView,
+        tile: &LiquidTile,
+        point: &[i32; 2],
+        threshold: f32,
+    ) -> bool {
+        let solid_index = solid.tiles.get(point).and_then(|t| t.get_index());
+        match self.partner {
+            Partner::Liquid(kind) => {
+                !solid.blocks_liquid(point)
+                    && liquid
+                        .get(point)
+                        .map_or(false, |t| t.kind == kind && !t.is_empty(threshold))
+            }
+            Partner::Solid(index) => {
+                let touches = match index {
+                    Some(index) => solid_index == Some(index),
+                    None => solid.collides(point),
+                };
+                touches && self.consumes(solid, point) <= tile.amount
+            }
+        }
+    }
+
+    /// Volume of the reacting liquid used up with the partner at `point`. Harder solid tiles
+    /// take more liquid to erode.
+    fn consumes(&self, solid: SolidView, point: &[i32; 2]) -> f32 {
+        match self.partner {
+            Partner::Liquid(_) => self.consumes,
+            Partner::Solid(_) => self.consumes * solid.get(point).map_or(1.0, |def| def.hardness),
+        }
+    }
+}
+
+/// Sent for every reaction that happens.
+#[derive(Debug, Clone)]
+pub struct LiquidReactionEvent {
+    pub entity: bevy::prelude::Entity,
+    /// Index into `LiquidReactions::reactions`.
+    pub reaction: usize,
+    pub coord: [i32; 2],
+    pub partner_coord: [i32; 2],
+    /// Total liquid volume used up by this reaction.
+    pub consumed: f32,
+}
+
+/// A reaction that happened during `GenericTiles::react`.
+#[derive(Debug, Clone, Copy)]
+pub struct ReactionOutcome {
+    pub reaction: usize,
+    pub coord: [i32; 2],
+    pub partner_coord: [i32; 2],
+    pub consumed: f32,
+}
+
+impl GenericTiles<i32, LiquidTile> {
+    /// Runs every reaction between liquid tiles and their neighbours, writing products into
+    /// `solid`. Liquid names are looked up in `kinds`, and tiles below `threshold` do not
+    /// react. Like the flow update, it skips sleeping chunks, so still liquid costs nothing.
+    /// Tiles are visited in sorted order, so the result does not depend on `HashMap` order.
+    pub fn react(
+        &mut self,
+        solid: &mut GenericTiles<i32, OptTileIndex>,
+        types: &TileTypes,
+        kinds: &LiquidKinds,
+        reactions: &LiquidReactions,
+        threshold: f32,
+    ) -> Vec<ReactionOutcome> {
+        let resolved = reactions.resolve(kinds);
+        let mut candidates = self
+            .indexed_awake_tiles()
+            .filter(|(_, tile)| !tile.is_empty(threshold))
+            .filter(|(_, tile)| resolved.iter().flatten().any(|r| r.liquid == tile.kind))
+            .map(|(coord, _)| coord)
+            .collect::<Vec<_>>();
+        candidates.sort_unstable();
+
+        let mut outcomes = Vec::new();
+        for coord in candidates {
+            // earlier reactions may have used this tile up already
+            let tile = self.get_or_default(&coord);
+            if tile.is_empty(threshold) {
+                continue;
+            }
+
+            let view = SolidView::new(solid, types);
+            let (index, partner_coord) =
+                match find_reaction(&resolved, self, view, coord, &tile, threshold) {
+                    Some(found) => found,
+                    None => continue,
+                };
+            let reaction = &reactions.reactions[index];
+            let volume = resolved[index].map_or(0.0, |r| r.consumes(view, &partner_coord));
+
+            let mut consumed = consume(self, &coord, volume);
+            if let Some(product) = reaction.product {
+                consumed += consume(self, &coord, f32::INFINITY);
+                solid.set(&coord, product);
+            }
+
+            if let ReactionPartner::Liquid(_) = &reaction.partner {
+                consumed += consume(self, &partner_coord, reaction.consumes_partner);
+            }
+            if let Some(product) = reaction.partner_product {
+                if let ReactionPartner::Liquid(_) = &reaction.partner {
+                    consumed += consume(self, &partner_coord, f32::INFINITY);
+                }
+                solid.set(&partner_coord, product);
+            }
+
+            outcomes.push(ReactionOutcome {
+                reaction: index,
+                coord,
+                partner_coord,
+                consumed,
+            });
+        }
+
+        outcomes
+    }
+}
+
+fn consume(liquid: &mut GenericTiles<i32, LiquidTile>, point: &[i32; 2], volume: f32) -> f32 {
+    match liquid.get_mut(point) {
+        Some(tile) => {
+            let consumed = volume.min(tile.amount);
+            tile.amount -= consumed;
+            consumed
+        }
+        None => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tiles::liquid::EMPTY_THRESHOLD;
+    use crate::tiles::liquid_kind::LiquidKindDef;
+    use crate::tiles::tile_type::TileDef;
+
+    #[test]
+    fn lava_and_water_make_stone() {
+        let reactions = LiquidReactions::default();
+        let types = TileTypes::default();
+        let mut liquid = GenericTiles::new(4);
+        let mut solid = GenericTiles::new(4);
+        liquid.set(&[0, 0], LiquidTile::with_kind(1.0, LiquidKind::LAVA));
+        liquid.set(&[1, 0], LiquidTile::with_kind(1.0, LiquidKind::WATER));
+
+        let outcomes = liquid.react(
+            &mut solid,
+            &types,
+            &LiquidKinds::default(),
+            &reactions,
+            EMPTY_THRESHOLD,
+        );
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].consumed, 1.5);
+        assert!(solid.get(&[0, 0]) == Some(&OptTileIndex::from_index(0)));
+        assert_eq!(liquid.get_or_default(&[0, 0]).amount, 0.0);
+        assert_eq!(liquid.get_or_default(&[1, 0]).amount, 0.5);
+    }
+
+    #[test]
+    fn acid_erodes_solid() {
+        let reactions = LiquidReactions::default();
+        let types = TileTypes::default();
+        let mut liquid = GenericTiles::new(4);
+        let mut solid = GenericTiles::new(4);
+        liquid.set(&[0, 1], LiquidTile::with_kind(1.0, LiquidKind::ACID));
+        solid.set(&[0, 0], OptTileIndex::from_index(0));
+
+        liquid.react(
+            &mut solid,
+            &types,
+            &LiquidKinds::default(),
+            &reactions,
+            EMPTY_THRESHOLD,
+        );
+        assert!(solid.get(&[0, 0]) == Some(&OptTileIndex::empty()));
+        assert_eq!(liquid.get_or_default(&[0, 1]).amount, 0.75);
+    }
+
+    #[test]
+    fn hardness_scales_erosion() {
+        let reactions = LiquidReactions::default();
+        let mut types = TileTypes::default();
+        let soft = types.register(TileDef {
+            hardness: 0.5,
+            ..TileDef::wall("sand", 0)
+        });
+        let hard = types.register(TileDef {
+            hardness: 8.0,
+            ..TileDef::wall("granite", 0)
+        });
+        let mut liquid = GenericTiles::new(4);
+        let mut solid = GenericTiles::new(4);
+        liquid.set(&[0, 1], LiquidTile::with_kind(1.0, LiquidKind::ACID));
+        liquid.set(&[2, 1], LiquidTile::with_kind(1.0, LiquidKind::ACID));
+        solid.set(&[0, 0], soft);
+        solid.set(&[2, 0], hard);
+
+        let outcomes = liquid.react(
+            &mut solid,
+            &types,
+            &LiquidKinds::default(),
+            &reactions,
+            EMPTY_THRESHOLD,
+        );
+        // granite takes more acid than the tile holds
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].consumed, 0.125);
+        assert!(solid.get(&[0, 0]) == Some(&OptTileIndex::empty()));
+        assert!(solid.get(&[2, 0]) == Some(&hard));
+    }
+
+    #[test]
+    fn sleeping_chunks_do_not_react() {
+        let reactions = LiquidReactions::default();
+        let types = TileTypes::default();
+        let kinds = LiquidKinds::default();
+        let mut liquid = GenericTiles::new(4);
+        let mut solid = GenericTiles::new(4);
+        liquid.set(&[0, 1], LiquidTile::with_kind(1.0, LiquidKind::ACID));
+        solid.set(&[0, 0], OptTileIndex::from_index(0));
+        liquid.chunk_mut(&[0, 0]).unwrap().record_activity(false, 0);
+
+        let mut react = |liquid: &mut GenericTiles<i32, LiquidTile>| {
+            liquid.react(&mut solid, &types, &kinds, &reactions, EMPTY_THRESHOLD)
+        };
+        assert!(react(&mut liquid).is_empty());
+        liquid.wake_around(&[0, 1]);
+        assert_eq!(react(&mut liquid).len(), 1);
+    }
+
+    #[test]
+    fn reactions_follow_kind_names() {
+        let reactions = LiquidReactions::from_ron(include_str!("../../assets/reactions.ron"));
+        let reactions = reactions.unwrap();
+        let types = TileTypes::default();
+
+        // a map that registered its kinds in another order
+        let mut kinds = LiquidKinds::default();
+        let magma = kinds.register(LiquidKindDef {
+            name: "magma".to_string(),
+            ..kinds.get(LiquidKind::LAVA).clone()
+        });
+        let mut liquid = GenericTiles::new(4);
+        let mut solid = GenericTiles::new(4);
+        liquid.set(&[0, 0], LiquidTile::with_kind(1.0, magma));
+        liquid.set(&[1, 0], LiquidTile::with_kind(1.0, LiquidKind::WATER));
+        assert!(liquid
+            .react(&mut solid, &types, &kinds, &reactions, EMPTY_THRESHOLD)
+            .is_empty());
+
+        let renamed = LiquidReactions {
+            reactions: vec![LiquidReaction {
+                liquid: "magma".to_string(),
+                ..reactions.reactions[0].clone()
+            }],
+        };
+        let outcomes = liquid.react(&mut solid, &types, &kinds, &renamed, EMPTY_THRESHOLD);
+        assert_eq!(outcomes.len(), 1);
+        assert!(solid.get(&[0, 0]) == Some(&OptTileIndex::from_index(0)));
+    }
+}
@@ -0,0 +1,154 @@
+use bevy::asset::HandleId;
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy::render::texture::TextureFormatPixelInfo;
+use bevy::sprite::Rect;
+
+use super::SolidTiles;
+
+/// Images the atlas of solid tiles is made of. They are stacked top to bottom and each one is
+/// cut into frames of `frame_size` row by row, so an image adds as many frames as fit in it.
+/// The atlas is put together once all images are loaded, until then nothing is drawn with it.
+pub struct SolidAtlasSources {
+    pub images: Vec<Handle<Image>>,
+    pub frame_size: Vec2,
+    /// Handle the atlas is stored under, which `SolidTiles` can use right away.
+    pub atlas: Handle<TextureAtlas>,
+    built: bool,
+}
+
+impl SolidAtlasSources {
+    pub fn new(
+        images: Vec<Handle<Image>>,
+        frame_size: Vec2,
+        atlases: &Assets<TextureAtlas>,
+    ) -> Self {
+        SolidAtlasSources {
+            images,
+            frame_size,
+            atlas: atlases.get_handle(HandleId::random::<TextureAtlas>()),
+            built: false,
+        }
+    }
+}
+
+/// Builds the atlas of `SolidAtlasSources` once its images are loaded.
+pub fn build_solid_atlas(
+    sources: Option<ResMut<SolidAtlasSources>>,
+    mut images: ResMut<Assets<Image>>,
+    mut atlases: ResMut<Assets<TextureAtlas>>,
+    mut maps: Query<&mut SolidTiles>,
+) {
+    let mut sources = match sources {
+        Some(sources) if !sources.built => sources,
+        _ => return,
+    };
+    let loaded = sources
+        .images
+        .iter()
+        .map(|handle| images.get(handle))
+        .collect::<Option<Vec<_>>>();
+    let (image, frames) = match loaded {
+        Some(loaded) => stack_images(&loaded, sources.frame_size),
+        None => return,
+    };
+
+    let size = image.size();
+    let mut atlas = TextureAtlas::new_empty(images.add(image), size);
+    for frame in frames {
+        atlas.add_texture(frame);
+    }
+    atlases.set_untracked(&sources.atlas, atlas);
+    sources.built = true;
+
+    // tiles drawn so far picked their variants without knowing the atlas
+    for mut solid in maps.iter_mut() {
+        solid.tiles.mark_all_modified();
+    }
+}
+
+/// Stacks `images` top to bottom into one image, with the frames of each one row by row. All
+/// images need the format of the first one, others are left out.
+fn stack_images(images: &[&Image], frame_size: Vec2) -> (Image, Vec<Rect>) {
+    let format = images
+        .first()
+        .map_or(TextureFormat::Rgba8UnormSrgb, |image| {
+            image.texture_descriptor.format
+        });
+    let images = images
+        .iter()
+        .filter(|image| image.texture_descriptor.format == format)
+        .collect::<Vec<_>>();
+    let pixel_size = format.pixel_size();
+    let size = |image: &Image| {
+        let size = image.texture_descriptor.size;
+        (size.width as usize, size.height as usize)
+    };
+    let width = images.iter().map(|image| size(image).0).max().unwrap_or(0);
+    let height = images.iter().map(|image| size(image).1).sum::<usize>();
+
+    let mut data = vec![0; width * height * pixel_size];
+    let mut frames = Vec::new();
+    let mut top = 0;
+    for image in images {
+        let (image_width, image_height) = size(image);
+        let row_len = image_width * pixel_size;
+        for (row, pixels) in image.data.chunks_exact(row_len).enumerate() {
+            let start = (top + row) * width * pixel_size;
+            data[start..start + row_len].copy_from_slice(pixels);
+        }
+
+        let columns = (image_width as f32 / frame_size.x) as usize;
+        let rows = (image_height as f32 / frame_size.y) as usize;
+        for row in 0..rows {
+            for column in 0..columns {
+                let min = Vec2::new(column as f32, row as f32) * frame_size;
+                let min = min + Vec2::new(0.0, top as f32);
+                frames.push(Rect {
+                    min,
+                    max: min + frame_size,
+                });
+            }
+        }
+        top += image_height;
+    }
+
+    let size = Extent3d {
+        width: width as u32,
+        height: height as u32,
+        depth_or_array_layers: 1,
+    };
+    (Image::new(size, TextureDimension::D2, data, format), frames)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn image(width: u32, height: u32, value: u8) -> Image {
+        let size = Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let format = TextureFormat::Rgba8UnormSrgb;
+        Image::new_fill(size, TextureDimension::D2, &[value; 4], format)
+    }
+
+    #[test]
+    fn frames_follow_the_image_sizes() {
+        let tilemap = image(32, 32, 1);
+        let terrain = image(64, 64, 2);
+        let (stacked, frames) = stack_images(&[&tilemap, &terrain], Vec2::splat(16.0));
+
+        assert_eq!(stacked.size(), Vec2::new(64.0, 96.0));
+        assert_eq!(frames.len(), 4 + 16);
+        assert_eq!(frames[3].min, Vec2::new(16.0, 16.0));
+        assert_eq!(frames[4].min, Vec2::new(0.0, 32.0));
+        assert_eq!(frames[19].max, Vec2::new(64.0, 96.0));
+
+        // the first image only fills the left half of its rows
+        let pixel = |x: usize, y: usize| stacked.data[(y * 64 + x) * 4];
+        assert_eq!([pixel(31, 31), pixel(32, 31), pixel(63, 95)], [1, 0, 2]);
+    }
+}
@@ -1,47 +1,346 @@
-use bevy::{prelude::*, render::texture::DEFAULT_IMAGE_HANDLE};
-use bevy_immediate::{ImmediateRenderObject, ImmediateRenderRequest};
+use bevy::{
+    prelude::*,
+    render::{camera::Camera2d, texture::DEFAULT_IMAGE_HANDLE},
+};
+use std::collections::{HashMap, HashSet};
 
 use super::*;
 
 pub trait Tile: Default + Send + Sync + 'static {
-    fn needs_sprite(&self) -> bool;
+    fn needs_sprite(&self, context: &SyncContext) -> bool;
+}
+
+/// Resources besides its own tiles that a `Tilemap` is drawn with.
+#[derive(SystemParam)]
+pub struct SyncContext<'w, 's> {
+    pub types: Res<'w, TileTypes>,
+    pub liquid: Res<'w, LiquidSimSettings>,
+    pub atlases: Res<'w, Assets<TextureAtlas>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
 }
 
 pub trait Tilemap: Send + Sync + 'static {
     type Tile: Tile;
     fn storage(&self) -> &GenericTiles<i32, Self::Tile>;
     fn storage_mut(&mut self) -> &mut GenericTiles<i32, Self::Tile>;
-    fn create_request(&self, tile: &Self::Tile) -> Vec<ImmediateRenderObject>;
+    fn create_objects(
+        &self,
+        coord: [i32; 2],
+        tile: &Self::Tile,
+        context: &SyncContext,
+    ) -> Vec<TileObject>;
+
+    /// Whether `create_objects` has anything to draw for a tile.
+    fn has_objects(&self, _coord: [i32; 2], tile: &Self::Tile, context: &SyncContext) -> bool {
+        tile.needs_sprite(context)
+    }
+
+    /// Whether the look of a tile depends on its neighbours, so the eight tiles around it have
+    /// to be rebuilt together with it.
+    fn rebuild_neighbours(&self) -> bool {
+        false
+    }
+}
+
+/// Size and depth the tiles of every map are drawn at.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TileRenderSettings {
+    /// Size of a tile before the transform of its map is applied.
+    pub tile_size: Vec2,
+    /// Depth of the tiles. The objects of a tile are stacked slightly above it.
+    pub z: f32,
+}
+
+/// Something drawn for a tile.
+pub enum TileObject {
+    /// `image` stretched over `size` and moved by `offset` from the tile centre, both in
+    /// tiles.
+    Sprite {
+        color: Color,
+        image: Handle<Image>,
+        offset: Vec2,
+        size: Vec2,
+    },
+    SheetSprite {
+        index: usize,
+        color: Color,
+        atlas: Handle<TextureAtlas>,
+    },
+    Label(TextSection),
 }
 
+impl TileObject {
+    /// A sprite covering the whole tile.
+    pub fn sprite(color: Color, image: Handle<Image>) -> Self {
+        TileObject::Sprite {
+            color,
+            image,
+            offset: Vec2::ZERO,
+            size: Vec2::ONE,
+        }
+    }
+
+    pub fn spawn(self, cmd: &mut Commands, center: Vec3, tile_size: Vec2) -> Entity {
+        match self {
+            TileObject::Sprite {
+                color,
+                image,
+                offset,
+                size,
+            } => cmd
+                .spawn_bundle(SpriteBundle {
+                    sprite: Sprite {
+                        color,
+                        custom_size: Some(size * tile_size),
+                        ..default()
+                    },
+                    texture: image,
+                    transform: Transform::from_translation(
+                        center + (offset * tile_size).extend(0.0),
+                    ),
+                    ..default()
+                })
+                .id(),
+            TileObject::SheetSprite {
+                index,
+                color,
+                atlas,
+            } => cmd
+                .spawn_bundle(SpriteSheetBundle {
+                    sprite: TextureAtlasSprite {
+                        index,
+                        color,
+                        custom_size: Some(tile_size),
+                        ..default()
+                    },
+                    texture_atlas: atlas,
+                    transform: Transform::from_translation(center),
+                    ..default()
+                })
+                .id(),
+            TileObject::Label(section) => cmd
+                .spawn_bundle(Text2dBundle {
+                    text: Text {
+                        sections: vec![section],
+                        alignment: TextAlignment {
+                            vertical: VerticalAlign::Center,
+                            horizontal: HorizontalAlign::Center,
+                        },
+                    },
+                    transform: Transform::from_translation(center),
+                    ..default()
+                })
+                .id(),
+        }
+    }
+}
+
+/// Entities drawn for every tile of a chunk, by inner coordinate.
+type ChunkEntities = HashMap<[usize; 2], Vec<Entity>>;
+
+/// Entities drawn for a map, by chunk coordinate.
+#[derive(Default)]
+pub struct RetainedChunks {
+    /// Chunk size of the storage the chunks were built from. Chunk coordinates mean something
+    /// else once it changes, e.g. after loading a map.
+    chunk_size: usize,
+    chunks: HashMap<[i32; 2], ChunkEntities>,
+}
+
+impl RetainedChunks {
+    fn despawn(&mut self, cmd: &mut Commands) {
+        self.chunks
+            .drain()
+            .flat_map(|(_, chunk)| chunk.into_values().flatten())
+            .for_each(|entity| cmd.entity(entity).despawn_recursive());
+    }
+}
+
+/// Tiles around the camera view that are kept built, so panning does not show chunks popping in.
+const VIEW_MARGIN: i32 = 4;
+
+/// Corners of the world space rectangle in view of a 2d camera.
+fn view_corners(transform: &GlobalTransform, projection: &OrthographicProjection) -> [Vec2; 4] {
+    [
+        [projection.left, projection.bottom],
+        [projection.right, projection.bottom],
+        [projection.right, projection.top],
+        [projection.left, projection.top],
+    ]
+    .map(|[x, y]| {
+        let local = Vec3::new(x, y, 0.0) * projection.scale;
+        transform.mul_vec3(local).truncate()
+    })
+}
+
+/// Lowest and highest tile coordinate of a map with the given `GlobalTransform` that lies in
+/// view, grown by `VIEW_MARGIN`.
+fn view_bounds(corners: &[Vec2; 4], map: &GlobalTransform, tile_size: Vec2) -> [[i32; 2]; 2] {
+    let to_map = map.compute_matrix().inverse();
+    let (min, max) = corners
+        .iter()
+        .map(|corner| to_map.transform_point3(corner.extend(0.0)).truncate() / tile_size)
+        .fold(
+            (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+            |(min, max), corner| (min.min(corner), max.max(corner)),
+        );
+    [
+        [
+            min.x.floor() as i32 - VIEW_MARGIN,
+            min.y.floor() as i32 - VIEW_MARGIN,
+        ],
+        [
+            max.x.ceil() as i32 + VIEW_MARGIN,
+            max.y.ceil() as i32 + VIEW_MARGIN,
+        ],
+    ]
+}
+
+/// Chunks of `storage` overlapping `bounds`, or all of them when there is no camera.
+fn visible_chunks<T: Default>(
+    storage: &GenericTiles<i32, T>,
+    bounds: Option<[[i32; 2]; 2]>,
+) -> HashSet<[i32; 2]> {
+    let [min, max] = match bounds {
+        Some([min, max]) => [
+            storage.point_to_chunk_coord(&min),
+            storage.point_to_chunk_coord(&max),
+        ],
+        None => return storage.chunks().keys().copied().collect(),
+    };
+    (min[0]..=max[0])
+        .flat_map(|x| (min[1]..=max[1]).map(move |y| [x, y]))
+        .filter(|coord| storage.chunks().contains_key(coord))
+        .collect()
+}
+
+/// Tiles of `storage` to rebuild, in sorted order: the modified tiles in `built` chunks, with
+/// `neighbours` also the tiles around any modified tile that lie in `built` chunks, and every
+/// tile of the `visible` chunks that are not built yet.
+fn dirty_tiles<T: Default>(
+    storage: &GenericTiles<i32, T>,
+    built: &HashMap<[i32; 2], ChunkEntities>,
+    visible: &HashSet<[i32; 2]>,
+    neighbours: bool,
+) -> Vec<[i32; 2]> {
+    let is_built = |coord: &[i32; 2]| built.contains_key(&storage.point_to_chunk_coord(coord));
+    let modified = storage.indexed_modified_tiles().map(|(coord, _)| coord);
+    // a tile written into a new chunk changes the look of the built chunk next to it
+    let mut dirty = if neighbours {
+        modified
+            .flat_map(|coord| coord::neighbourhood(&coord))
+            .filter(is_built)
+            .collect::<Vec<_>>()
+    } else {
+        modified.filter(is_built).collect()
+    };
+    for chunk_coord in visible.iter().filter(|coord| !built.contains_key(*coord)) {
+        let chunk = &storage.chunks()[chunk_coord];
+        dirty.extend(
+            chunk
+                .indexed_tiles()
+                .map(|(inner_coord, _)| storage.combine_coord(chunk_coord, &inner_coord)),
+        );
+    }
+    dirty.sort_unstable();
+    dirty.dedup();
+    dirty
+}
+
+/// Keeps an entity for every object of every tile in chunks around the camera view and only
+/// rebuilds the tiles in the `modified` lists, which are cleared afterwards. Chunks that left
+/// the view or disappeared from the storage are despawned, chunks that came into view are
+/// built whole. Tile entities are children of the map entity, so they follow its transform.
 pub fn tiles_sync<T: Tilemap + Component>(
-    mut tiles: Query<&mut T>,
-    mut requests: EventWriter<ImmediateRenderRequest>,
+    mut cmd: Commands,
+    mut retained: Local<HashMap<Entity, RetainedChunks>>,
+    settings: Res<TileRenderSettings>,
+    context: SyncContext,
+    camera: Query<(&GlobalTransform, &OrthographicProjection), With<Camera2d>>,
+    mut tiles: Query<(Entity, &GlobalTransform, &mut T)>,
 ) {
-    for tiles in tiles.iter_mut() {
-        for (coord, tile) in tiles.storage().indexed_tiles() {
-            if tile.needs_sprite() {
-                requests.send_batch(tiles.create_request(tile).into_iter().enumerate().map(
-                    |(i, obj)| ImmediateRenderRequest {
-                        obj,
-                        z: i as f32 * 0.1,
-                        pos: IVec2::new(coord[0], coord[1]),
-                    },
-                ));
+    retained.retain(|entity, map| {
+        let alive = tiles.get(*entity).is_ok();
+        if !alive {
+            map.despawn(&mut cmd);
+        }
+        alive
+    });
+
+    let corners = camera
+        .iter()
+        .next()
+        .map(|(transform, projection)| view_corners(transform, projection));
+
+    for (entity, transform, mut tiles) in tiles.iter_mut() {
+        // every write pushes another entry, a busy liquid step repeats each tile many times
+        tiles.storage_mut().deduplicate_modified();
+        let storage = tiles.storage();
+        let bounds = corners
+            .as_ref()
+            .map(|corners| view_bounds(corners, transform, settings.tile_size));
+        let visible = visible_chunks(storage, bounds);
+
+        let map = retained.entry(entity).or_default();
+        if map.chunk_size != storage.chunk_size() {
+            map.despawn(&mut cmd);
+            map.chunk_size = storage.chunk_size();
+        }
+        let chunks = &mut map.chunks;
+        chunks.retain(|chunk_coord, chunk| {
+            let keep = visible.contains(chunk_coord);
+            if !keep {
+                chunk
+                    .values()
+                    .flatten()
+                    .for_each(|entity| cmd.entity(*entity).despawn_recursive());
             }
+            keep
+        });
+
+        let dirty = dirty_tiles(storage, chunks, &visible, tiles.rebuild_neighbours());
+        let mut spawned = Vec::new();
+        for coord in dirty {
+            let (chunk_coord, inner_coord) = storage.split_coord(&coord);
+            let chunk = chunks.entry(chunk_coord).or_default();
+            for entity in chunk.remove(&inner_coord).into_iter().flatten() {
+                cmd.entity(entity).despawn_recursive();
+            }
+
+            let tile = match storage.get(&coord) {
+                Some(tile) if tiles.has_objects(coord, tile, &context) => tile,
+                _ => continue,
+            };
+            let center = (Vec2::new(coord[0] as f32, coord[1] as f32) * settings.tile_size)
+                .extend(settings.z);
+            let entities = tiles
+                .create_objects(coord, tile, &context)
+                .into_iter()
+                .enumerate()
+                .map(|(i, obj)| {
+                    let z = Vec3::Z * i as f32 * 0.1;
+                    obj.spawn(&mut cmd, center + z, settings.tile_size)
+                })
+                .collect::<Vec<_>>();
+            spawned.extend_from_slice(&entities);
+            chunk.insert(inner_coord, entities);
         }
+        cmd.entity(entity).push_children(&spawned);
+
+        tiles.storage_mut().clear_modified();
     }
 }
 
 impl Tile for OptTileIndex {
-    fn needs_sprite(&self) -> bool {
+    fn needs_sprite(&self, _context: &SyncContext) -> bool {
         self.get_index().is_some()
     }
 }
 
 impl Tile for LiquidTile {
-    fn needs_sprite(&self) -> bool {
-        !self.is_empty()
+    fn needs_sprite(&self, context: &SyncContext) -> bool {
+        !self.is_empty(context.liquid.empty_threshold)
     }
 }
 
@@ -56,13 +355,28 @@ impl Tilemap for SolidTiles {
         &mut self.tiles
     }
 
-    fn create_request(&self, tile: &Self::Tile) -> Vec<ImmediateRenderObject> {
-        vec![ImmediateRenderObject::SheetSprite {
-            index: tile.get_index().unwrap() as usize,
+    fn create_objects(
+        &self,
+        coord: [i32; 2],
+        _tile: &Self::Tile,
+        context: &SyncContext,
+    ) -> Vec<TileObject> {
+        let atlas_len = context
+            .atlases
+            .get(&self.atlas)
+            .map_or(0, |atlas| atlas.len());
+        let index = context.types.atlas_index(&self.tiles, coord, atlas_len);
+        vec![TileObject::SheetSprite {
+            index: index.unwrap() as usize,
             color: Color::WHITE,
             atlas: self.atlas.clone(),
         }]
     }
+
+    /// Autotiled types change with their neighbours.
+    fn rebuild_neighbours(&self) -> bool {
+        true
+    }
 }
 
 impl Tilemap for LiquidTiles {
@@ -76,22 +390,148 @@ impl Tilemap for LiquidTiles {
         &mut self.tiles
     }
 
-    fn create_request(&self, tile: &Self::Tile) -> Vec<ImmediateRenderObject> {
-        vec![
-            ImmediateRenderObject::Sprite {
-                //color: Color::rgb((tile.velocity.x - tile.velocity.z), tile.velocity.y - tile.velocity.w, 0.0),
-                //image: DEFAULT_IMAGE_HANDLE.typed(),
-                color: Color::WHITE,
-                image: self.image.clone(),
-            },
-            ImmediateRenderObject::Label(TextSection {
+    fn create_objects(
+        &self,
+        coord: [i32; 2],
+        tile: &Self::Tile,
+        context: &SyncContext,
+    ) -> Vec<TileObject> {
+        let threshold = context.liquid.empty_threshold;
+        let color = self.kinds.get(tile.kind).color;
+        let mut objects = match self.render.mode {
+            _ if !tile.needs_sprite(context) => vec![],
+            LiquidRenderMode::Tiles => vec![TileObject::sprite(
+                //Color::rgb((tile.velocity.x - tile.velocity.z), tile.velocity.y - tile.velocity.w, 0.0),
+                //DEFAULT_IMAGE_HANDLE.typed(),
+                color,
+                self.image.clone(),
+            )],
+            LiquidRenderMode::Level => liquid_render::level_objects(
+                &self.tiles,
+                coord,
+                tile,
+                threshold,
+                color,
+                &self.image,
+            ),
+        };
+        if self.render.debug_labels {
+            objects.push(TileObject::Label(TextSection {
                 value: tile.to_string(),
                 style: TextStyle {
                     font: self.font.clone(),
                     font_size: 10.0,
                     color: Color::WHITE,
                 },
-            }),
-        ]
+            }));
+        }
+        if let Some(feature) = self.features.get(&coord) {
+            let (marker, color) = match *feature {
+                LiquidFeature::Source { liquid, .. } => ("+", self.kinds.get(liquid).color),
+                LiquidFeature::Drain { .. } => ("-", Color::WHITE),
+            };
+            objects.push(TileObject::Label(TextSection {
+                value: marker.to_string(),
+                style: TextStyle {
+                    font: self.font.clone(),
+                    font_size: 16.0,
+                    color,
+                },
+            }));
+        }
+        objects
+    }
+
+    /// Sources and drains are drawn on empty tiles too.
+    fn has_objects(&self, coord: [i32; 2], tile: &Self::Tile, context: &SyncContext) -> bool {
+        tile.needs_sprite(context) || self.features.get(&coord).is_some()
+    }
+
+    fn rebuild_neighbours(&self) -> bool {
+        self.render.mode == LiquidRenderMode::Level
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dirty_tiles_cover_modified_neighbours_and_new_chunks() {
+        let mut storage = GenericTiles::<i32, bool>::new(4);
+        storage.set(&[0, 0], true);
+        storage.set(&[4, 0], true);
+        storage.set(&[0, 4], true);
+        storage.clear_modified();
+        storage.set(&[3, 3], false);
+        storage.set(&[-1, 1], true);
+
+        // chunk [0, 0] is built, [1, 0] just came into view, [0, 1] is out of view and [-1, 0]
+        // was just created out of view
+        let built = HashMap::from([([0, 0], ChunkEntities::new())]);
+        let visible = HashSet::from([[0, 0], [1, 0]]);
+        let new_chunk = (4..8).flat_map(|x| (0..4).map(move |y| [x, y]));
+
+        let dirty = dirty_tiles(&storage, &built, &visible, false);
+        let mut expected = new_chunk.clone().chain([[3, 3]]).collect::<Vec<_>>();
+        expected.sort_unstable();
+        assert_eq!(dirty, expected);
+
+        // neighbours in built chunks are rebuilt, also those of the tile in the new chunk, the
+        // ones in [0, 1] are not
+        let dirty = dirty_tiles(&storage, &built, &visible, true);
+        let mut expected = new_chunk
+            .chain([[2, 2], [2, 3], [3, 2], [3, 3]])
+            .chain([[0, 0], [0, 1], [0, 2]])
+            .collect::<Vec<_>>();
+        expected.sort_unstable();
+        assert_eq!(dirty, expected);
+    }
+
+    fn rect(min: Vec2, max: Vec2) -> [Vec2; 4] {
+        [min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)]
+    }
+
+    #[test]
+    fn view_bounds_grow_by_the_margin() {
+        let corners = rect(Vec2::new(-40.0, -8.0), Vec2::new(24.0, 40.0));
+        let bounds = view_bounds(&corners, &GlobalTransform::identity(), Vec2::splat(16.0));
+        // -2.5..1.5 and -0.5..2.5 tiles, rounded outwards
+        assert_eq!(
+            bounds,
+            [
+                [-3 - VIEW_MARGIN, -1 - VIEW_MARGIN],
+                [2 + VIEW_MARGIN, 3 + VIEW_MARGIN]
+            ]
+        );
+    }
+
+    #[test]
+    fn view_bounds_follow_the_map_transform() {
+        let corners = rect(Vec2::new(100.0, 50.0), Vec2::new(164.0, 114.0));
+        let map = Transform::from_xyz(100.0, 50.0, 0.0).with_scale(Vec3::splat(2.0));
+        let bounds = view_bounds(&corners, &GlobalTransform::from(map), Vec2::splat(16.0));
+        assert_eq!(
+            bounds,
+            [
+                [-VIEW_MARGIN, -VIEW_MARGIN],
+                [2 + VIEW_MARGIN, 2 + VIEW_MARGIN]
+            ]
+        );
+    }
+
+    #[test]
+    fn visible_chunks_overlap_the_bounds() {
+        let mut storage = GenericTiles::<i32, bool>::new(4);
+        for point in [[-1, -1], [0, 0], [12, 0]] {
+            storage.set(&point, true);
+        }
+
+        let visible = visible_chunks(&storage, Some([[-7, -5], [6, 7]]));
+        assert_eq!(visible, HashSet::from([[-1, -1], [0, 0]]));
+        let visible = visible_chunks(&storage, Some([[-2, 0], [-1, 3]]));
+        assert_eq!(visible, HashSet::new());
+        let visible = visible_chunks(&storage, None);
+        assert_eq!(visible, HashSet::from([[-1, -1], [0, 0], [3, 0]]));
     }
 }
@@ -0,0 +1,272 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use super::format::WorldLayers;
+use super::generic_tiles::*;
+use super::liquid::LiquidTile;
+use super::liquid_feature::LiquidFeatures;
+use super::OptTileIndex;
+
+#[derive(Debug)]
+pub enum TextFormatError {
+    Io(std::io::Error),
+    Ron(ron::Error),
+    Json(serde_json::Error),
+    InvalidChunkSize(usize),
+    /// The solid and liquid layers have different chunk sizes.
+    LayerChunkSizeMismatch {
+        solid: usize,
+        liquid: usize,
+    },
+    ChunkSizeMismatch {
+        chunk_size: usize,
+        found: (usize, usize),
+    },
+}
+
+impl std::fmt::Display for TextFormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TextFormatError::Io(err) => write!(f, "io error: {}", err),
+            TextFormatError::Ron(err) => write!(f, "ron error: {}", err),
+            TextFormatError::Json(err) => write!(f, "json error: {}", err),
+            TextFormatError::InvalidChunkSize(size) => write!(f, "invalid chunk size {}", size),
+            TextFormatError::LayerChunkSizeMismatch { solid, liquid } => write!(
+                f,
+                "solid and liquid layers have different chunk sizes ({} and {})",
+                solid, liquid
+            ),
+            TextFormatError::ChunkSizeMismatch { chunk_size, found } => write!(
+                f,
+                "chunk of size {}x{} in a layer with chunk size {}",
+                found.0, found.1, chunk_size
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TextFormatError {}
+
+impl From<std::io::Error> for TextFormatError {
+    fn from(err: std::io::Error) -> Self {
+        TextFormatError::Io(err)
+    }
+}
+
+impl From<ron::Error> for TextFormatError {
+    fn from(err: ron::Error) -> Self {
+        TextFormatError::Ron(err)
+    }
+}
+
+impl From<serde_json::Error> for TextFormatError {
+    fn from(err: serde_json::Error) -> Self {
+        TextFormatError::Json(err)
+    }
+}
+
+#[derive(Serialize)]
+struct WorldRef<'a> {
+    solid: &'a GenericTiles<i32, OptTileIndex>,
+    liquid: &'a GenericTiles<i32, LiquidTile>,
+    features: &'a LiquidFeatures,
+}
+
+#[derive(Deserialize)]
+struct World {
+    solid: GenericTiles<i32, OptTileIndex>,
+    liquid: GenericTiles<i32, LiquidTile>,
+    #[serde(default)]
+    features: LiquidFeatures,
+}
+
+impl World {
+    fn validate(self) -> Result<WorldLayers, TextFormatError> {
+        if self.solid.chunk_size() == 0 {
+            return Err(TextFormatError::InvalidChunkSize(0));
+        }
+        if self.solid.chunk_size() != self.liquid.chunk_size() {
+            return Err(TextFormatError::LayerChunkSizeMismatch {
+                solid: self.solid.chunk_size(),
+                liquid: self.liquid.chunk_size(),
+            });
+        }
+        check_chunk_sizes(&self.solid)?;
+        check_chunk_sizes(&self.liquid)?;
+        Ok((self.solid, self.liquid, self.features))
+    }
+}
+
+fn check_chunk_sizes<T: Default>(tiles: &GenericTiles<i32, T>) -> Result<(), TextFormatError> {
+    let chunk_size = tiles.chunk_size();
+    for (_, chunk) in tiles.indexed_chunks() {
+        let found = chunk.tiles().dim();
+        if found != (chunk_size, chunk_size) {
+            return Err(TextFormatError::ChunkSizeMismatch { chunk_size, found });
+        }
+    }
+    Ok(())
+}
+
+pub fn to_ron(
+    solid: &GenericTiles<i32, OptTileIndex>,
+    liquid: &GenericTiles<i32, LiquidTile>,
+    features: &LiquidFeatures,
+) -> Result<String, TextFormatError> {
+    let config = ron::ser::PrettyConfig::new().indentor("  ".to_string());
+    Ok(ron::ser::to_string_pretty(
+        &WorldRef {
+            solid,
+            liquid,
+            features,
+        },
+        config,
+    )?)
+}
+
+pub fn from_ron(text: &str) -> Result<WorldLayers, TextFormatError> {
+    ron::de::from_str::<World>(text)?.validate()
+}
+
+pub fn to_json(
+    solid: &GenericTiles<i32, OptTileIndex>,
+    liquid: &GenericTiles<i32, LiquidTile>,
+    features: &LiquidFeatures,
+) -> Result<String, TextFormatError> {
+    Ok(serde_json::to_string_pretty(&WorldRef {
+        solid,
+        liquid,
+        features,
+    })?)
+}
+
+pub fn from_json(text: &str) -> Result<WorldLayers, TextFormatError> {
+    serde_json::from_str::<World>(text)?.validate()
+}
+
+/// Saves the world as RON or JSON, depending on the file extension.
+pub fn save_world_text_file(
+    path: impl AsRef<Path>,
+    solid: &GenericTiles<i32, OptTileIndex>,
+    liquid: &GenericTiles<i32, LiquidTile>,
+    features: &LiquidFeatures,
+) -> Result<(), TextFormatError> {
+    let text = if is_json(path.as_ref()) {
+        to_json(solid, liquid, features)?
+    } else {
+        to_ron(solid, liquid, features)?
+    };
+    std::fs::write(path, text)?;
+    Ok(())
+}
+
+pub fn load_world_text_file(path: impl AsRef<Path>) -> Result<WorldLayers, TextFormatError> {
+    let text = std::fs::read_to_string(path.as_ref())?;
+    if is_json(path.as_ref()) {
+        from_json(&text)
+    } else {
+        from_ron(&text)
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| ext == "json")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tiles::liquid_feature::LiquidFeature;
+    use crate::tiles::liquid_kind::LiquidKind;
+    use bevy::prelude::Vec4;
+
+    fn sample_world(points: &[[i32; 2]]) -> WorldLayers {
+        let mut solid = GenericTiles::new(4);
+        let mut liquid = GenericTiles::new(4);
+        let mut features = LiquidFeatures::default();
+        for point in points {
+            let i = (point[0] * 3 + point[1]).rem_euclid(7);
+            solid.set(point, OptTileIndex::from_index(i as u32));
+            liquid.set(
+                &[point[0], point[1] + 1],
+                LiquidTile {
+                    amount: i as f32 * 0.5,
+                    velocity: Vec4::new(0.0, 1.0, 0.0, -1.0),
+                    kind: LiquidKind(i as u16 % 3),
+                },
+            );
+            features.insert(*point, LiquidFeature::Drain { rate: i as f32 });
+        }
+        (solid, liquid, features)
+    }
+
+    const POINTS: [[i32; 2]; 4] = [[0, 0], [-1, -1], [-9, 5], [7, -20]];
+
+    fn assert_same(
+        (solid_a, liquid_a, features_a): &WorldLayers,
+        (solid_b, liquid_b, features_b): &WorldLayers,
+    ) {
+        assert_eq!(solid_a.chunks().len(), solid_b.chunks().len());
+        for (coord, tile) in solid_a.indexed_tiles() {
+            assert!(solid_b.get(&coord) == Some(tile));
+        }
+
+        assert_eq!(liquid_a.chunks().len(), liquid_b.chunks().len());
+        for (coord, tile) in liquid_a.indexed_tiles() {
+            let other = liquid_b.get(&coord).unwrap();
+            assert_eq!(tile.amount, other.amount);
+            assert_eq!(tile.velocity, other.velocity);
+            assert_eq!(tile.kind, other.kind);
+        }
+
+        assert!(features_a.iter().eq(features_b.iter()));
+    }
+
+    #[test]
+    fn ron_round_trip() {
+        let world = sample_world(&POINTS);
+        let text = to_ron(&world.0, &world.1, &world.2).unwrap();
+        assert_same(&world, &from_ron(&text).unwrap());
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let world = sample_world(&POINTS);
+        let text = to_json(&world.0, &world.1, &world.2).unwrap();
+        assert_same(&world, &from_json(&text).unwrap());
+    }
+
+    #[test]
+    fn stable_chunk_order() {
+        let mut reversed = POINTS;
+        reversed.reverse();
+
+        let a = sample_world(&POINTS);
+        let b = sample_world(&reversed);
+        assert_eq!(
+            to_ron(&a.0, &a.1, &a.2).unwrap(),
+            to_ron(&b.0, &b.1, &b.2).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_bad_chunk_sizes() {
+        let (solid, liquid, features) = sample_world(&[]);
+        let text = to_ron(&solid, &liquid, &features).unwrap();
+        let zero = text.replace("chunk_size: 4", "chunk_size: 0");
+        assert!(matches!(
+            from_ron(&zero),
+            Err(TextFormatError::InvalidChunkSize(0))
+        ));
+
+        let liquid = GenericTiles::<i32, LiquidTile>::new(8);
+        let text = to_json(&solid, &liquid, &features).unwrap();
+        assert!(matches!(
+            from_json(&text),
+            Err(TextFormatError::LayerChunkSizeMismatch {
+                solid: 4,
+                liquid: 8
+            })
+        ));
+    }
+}
@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::autotile::AutotileRule;
+use super::generic_tiles::*;
+use super::OptTileIndex;
+
+/// Value of a custom tile property.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TileProperty {
+    Bool(bool),
+    Int(i64),
+    Float(f32),
+    Text(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TileDef {
+    pub name: String,
+    /// Atlas index the tile is drawn with, or the first variant when it is autotiled.
+    pub atlas_index: u32,
+    /// Whether things collide with the tile.
+    pub collision: bool,
+    /// Share of liquid flow that passes into the tile, from 0 for a wall to 1 for open space.
+    pub permeability: f32,
+    /// How hard the tile is to erode. Reactions with the tile use up this many times the liquid.
+    pub hardness: f32,
+    #[serde(default)]
+    pub autotile: Option<AutotileRule>,
+    #[serde(default)]
+    pub properties: HashMap<String, TileProperty>,
+}
+
+impl TileDef {
+    /// A tile that collides and blocks liquid.
+    pub fn wall(name: &str, atlas_index: u32) -> Self {
+        TileDef {
+            name: name.to_string(),
+            atlas_index,
+            collision: true,
+            permeability: 0.0,
+            hardness: 1.0,
+            autotile: None,
+            properties: HashMap::new(),
+        }
+    }
+}
+
+/// Every tile type a `SolidTiles` layer can hold. The index stored in a tile is the index of
+/// its type in here.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TileTypes {
+    types: Vec<TileDef>,
+}
+
+impl Default for TileTypes {
+    fn default() -> Self {
+        TileTypes {
+            types: vec![TileDef::wall("stone", 0), TileDef::wall("grass", 1)],
+        }
+    }
+}
+
+impl TileTypes {
+    /// Fails without any types, as unknown indices fall back to the first one.
+    pub fn from_ron(text: &str) -> Result<Self, ron::Error> {
+        let types: TileTypes = ron::de::from_str(text)?;
+        if types.types.is_empty() {
+            return Err(serde::de::Error::custom("no tile types"));
+        }
+        Ok(types)
+    }
+
+    pub fn register(&mut self, def: TileDef) -> OptTileIndex {
+        self.types.push(def);
+        OptTileIndex::from_index((self.types.len() - 1) as u32)
+    }
+
+    /// Unknown types, e.g. from a map saved with more types registered, behave like the first
+    /// one.
+    pub fn get(&self, index: u32) -> &TileDef {
+        self.types.get(index as usize).unwrap_or(&self.types[0])
+    }
+
+    /// Definition of a tile, or `None` for an empty tile.
+    pub fn tile(&self, tile: &OptTileIndex) -> Option<&TileDef> {
+        tile.get_index().map(|index| self.get(index))
+    }
+
+    pub fn find(&self, name: &str) -> Option<OptTileIndex> {
+        self.types
+            .iter()
+            .position(|def| def.name == name)
+            .map(|index| OptTileIndex::from_index(index as u32))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (OptTileIndex, &TileDef)> {
+        self.types
+            .iter()
+            .enumerate()
+            .map(|(index, def)| (OptTileIndex::from_index(index as u32), def))
+    }
+
+    /// The type after `index`, wrapping around to the first one.
+    pub fn next(&self, index: u32) -> u32 {
+        (index + 1) % self.types.len() as u32
+    }
+}
+
+/// A solid layer together with the definitions of its tiles, as read by the liquid step.
+#[derive(Clone, Copy)]
+pub struct SolidView<'a> {
+    pub tiles: &'a GenericTiles<i32, OptTileIndex>,
+    pub types: &'a TileTypes,
+}
+
+impl<'a> SolidView<'a> {
+    pub fn new(tiles: &'a GenericTiles<i32, OptTileIndex>, types: &'a TileTypes) -> Self {
+        SolidView { tiles, types }
+    }
+
+    pub fn get(&self, point: &[i32; 2]) -> Option<&'a TileDef> {
+        self.types.tile(self.tiles.get(point)?)
+    }
+
+    /// Empty tiles let all liquid through.
+    pub fn permeability(&self, point: &[i32; 2]) -> f32 {
+        self.get(point).map_or(1.0, |def| def.permeability)
+    }
+
+    pub fn blocks_liquid(&self, point: &[i32; 2]) -> bool {
+        self.permeability(point) <= 0.0
+    }
+
+    pub fn collides(&self, point: &[i32; 2]) -> bool {
+        self.get(point).map_or(false, |def| def.collision)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shipped_palette_covers_the_tilemap() {
+        let types = TileTypes::from_ron(include_str!("../../assets/tile_types.ron")).unwrap();
+        // the four frames of tilemap.png
+        for frame in 0..4 {
+            assert!(types.iter().any(|(_, def)| def.atlas_index == frame));
+        }
+    }
+
+    #[test]
+    fn empty_types_are_rejected() {
+        assert!(TileTypes::from_ron("(types: [])").is_err());
+    }
+
+    #[test]
+    fn shipped_types_keep_the_default_indices() {
+        let types = TileTypes::from_ron(include_str!("../../assets/tile_types.ron")).unwrap();
+        for (index, def) in TileTypes::default().iter() {
+            assert_eq!(types.find(&def.name), Some(index));
+        }
+    }
+}